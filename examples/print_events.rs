@@ -15,14 +15,14 @@ fn main() {
         dbus_name: "my_player",
         display_name: "My Player",
         hwnd,
+        ..Default::default()
     };
 
-    let mut controls = MediaControls::new(config).unwrap();
-
     // The closure must be Send and have a static lifetime.
-    controls
-        .attach(|event: MediaControlEvent| println!("Event received: {:?}", event))
-        .unwrap();
+    let mut controls = MediaControls::start(config, |event: MediaControlEvent| {
+        println!("Event received: {:?}", event)
+    })
+    .unwrap();
 
     // Update the media metadata.
     controls
@@ -35,6 +35,7 @@ fn main() {
         dbus_name: "my_player",
         display_name: "My Player",
         hwnd,
+        ..Default::default()
     };
 
     let mut controls = MediaControls::new(config).unwrap();
@@ -54,8 +55,19 @@ fn main() {
             title: Some("When The Sun Hits"),
             album: Some("Souvlaki"),
             artist: Some("Slowdive"),
+            artists: None,
+            album_artists: None,
+            track_number: None,
+            disc_number: None,
+            genre: None,
+            content_created: None,
             duration: Some(Duration::from_secs_f64(4.0 * 60.0 + 50.0)),
             cover_url: Some("https://c.pxhere.com/photos/34/c1/souvlaki_authentic_greek_greek_food_mezes-497780.jpg!d"),
+            cover_bytes: None,
+            live: false,
+            track_id: None,
+            chapters: None,
+            extra: None,
         })
         .unwrap();
 
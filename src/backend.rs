@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::{
+    Error, ImageFormat, MediaButton, MediaControlEvent, MediaControls, MediaMetadata,
+    MediaPlayback, MetadataValue, TrackId,
+};
+
+/// The part of [`MediaControls`] used by most applications, so that code written against
+/// it can be tested with [`MockControls`] instead of the platform's media controls.
+pub trait MediaControlsBackend {
+    /// Attach the media control events to a handler.
+    fn attach<F>(&mut self, event_handler: F) -> Result<(), Error>
+    where
+        F: Fn(MediaControlEvent) + Send + 'static;
+
+    /// Detach the event handler.
+    fn detach(&mut self) -> Result<(), Error>;
+
+    /// Set the metadata of the currently playing media item.
+    fn set_metadata(&mut self, metadata: MediaMetadata) -> Result<(), Error>;
+
+    /// Set the current playback status.
+    fn set_playback(&mut self, playback: MediaPlayback) -> Result<(), Error>;
+
+    /// Set the volume level, where 1.0 is full volume. Only served on MPRIS.
+    fn set_volume(&mut self, volume: f64) -> Result<(), Error>;
+
+    /// Enable or disable a specific media control button.
+    fn set_button_enabled(&mut self, button: MediaButton, enabled: bool) -> Result<(), Error>;
+}
+
+impl MediaControlsBackend for MediaControls {
+    fn attach<F>(&mut self, event_handler: F) -> Result<(), Error>
+    where
+        F: Fn(MediaControlEvent) + Send + 'static,
+    {
+        MediaControls::attach(self, event_handler)
+    }
+
+    fn detach(&mut self) -> Result<(), Error> {
+        MediaControls::detach(self)
+    }
+
+    fn set_metadata(&mut self, metadata: MediaMetadata) -> Result<(), Error> {
+        MediaControls::set_metadata(self, metadata)
+    }
+
+    fn set_playback(&mut self, playback: MediaPlayback) -> Result<(), Error> {
+        MediaControls::set_playback(self, playback)
+    }
+
+    #[cfg(all(
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    ))]
+    fn set_volume(&mut self, volume: f64) -> Result<(), Error> {
+        MediaControls::set_volume(self, volume)
+    }
+
+    #[cfg(not(all(
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    )))]
+    fn set_volume(&mut self, _volume: f64) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn set_button_enabled(&mut self, button: MediaButton, enabled: bool) -> Result<(), Error> {
+        MediaControls::set_button_enabled(self, button, enabled)
+    }
+}
+
+/// A call made to [`MockControls`].
+#[derive(Clone, PartialEq, Debug)]
+pub enum MockCall {
+    Attach,
+    Detach,
+    SetMetadata(RecordedMetadata),
+    SetPlayback(MediaPlayback),
+    SetVolume(f64),
+    SetButtonEnabled(MediaButton, bool),
+}
+
+/// An owned copy of the [`MediaMetadata`] passed to [`MockControls`].
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct RecordedMetadata {
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub artist: Option<String>,
+    pub artists: Option<Vec<String>>,
+    pub album_artists: Option<Vec<String>>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub genre: Option<Vec<String>>,
+    pub content_created: Option<String>,
+    pub cover_url: Option<String>,
+    pub cover_bytes: Option<(ImageFormat, Vec<u8>)>,
+    pub duration: Option<Duration>,
+    pub live: bool,
+    pub track_id: Option<TrackId>,
+    pub chapters: Option<Vec<(Duration, String)>>,
+    pub extra: Option<HashMap<String, MetadataValue>>,
+}
+
+impl From<MediaMetadata<'_>> for RecordedMetadata {
+    fn from(metadata: MediaMetadata) -> Self {
+        fn to_strings(strings: Option<Vec<&str>>) -> Option<Vec<String>> {
+            strings.map(|strings| strings.into_iter().map(str::to_owned).collect())
+        }
+
+        Self {
+            title: metadata.title.map(str::to_owned),
+            album: metadata.album.map(str::to_owned),
+            artist: metadata.artist.map(str::to_owned),
+            artists: to_strings(metadata.artists),
+            album_artists: to_strings(metadata.album_artists),
+            track_number: metadata.track_number,
+            disc_number: metadata.disc_number,
+            genre: to_strings(metadata.genre),
+            content_created: metadata.content_created.map(str::to_owned),
+            cover_url: metadata.cover_url.map(str::to_owned),
+            cover_bytes: metadata
+                .cover_bytes
+                .map(|(format, bytes)| (format, bytes.to_vec())),
+            duration: metadata.duration,
+            live: metadata.live,
+            track_id: metadata.track_id.cloned(),
+            chapters: metadata.chapters.map(|chapters| {
+                chapters
+                    .into_iter()
+                    .map(|(offset, title)| (offset, title.to_owned()))
+                    .collect()
+            }),
+            extra: metadata.extra.map(|extra| {
+                extra
+                    .into_iter()
+                    .map(|(key, value)| (key.to_owned(), value))
+                    .collect()
+            }),
+        }
+    }
+}
+
+/// A [`MediaControlsBackend`] recording the calls made to it, to be checked in tests.
+/// Every call succeeds. Events from clients are simulated with [`MockControls::emit`].
+#[derive(Default)]
+pub struct MockControls {
+    /// The calls made so far, in order.
+    pub calls: Vec<MockCall>,
+    event_handler: Option<Box<dyn Fn(MediaControlEvent) + Send>>,
+}
+
+impl MockControls {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send an event to the attached handler, as a client would. Does nothing if detached.
+    pub fn emit(&self, event: MediaControlEvent) {
+        if let Some(event_handler) = &self.event_handler {
+            event_handler(event);
+        }
+    }
+
+    /// Whether an event handler is attached.
+    pub fn is_attached(&self) -> bool {
+        self.event_handler.is_some()
+    }
+}
+
+impl std::fmt::Debug for MockControls {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockControls")
+            .field("calls", &self.calls)
+            .field("attached", &self.is_attached())
+            .finish()
+    }
+}
+
+impl MediaControlsBackend for MockControls {
+    fn attach<F>(&mut self, event_handler: F) -> Result<(), Error>
+    where
+        F: Fn(MediaControlEvent) + Send + 'static,
+    {
+        self.calls.push(MockCall::Attach);
+        self.event_handler = Some(Box::new(event_handler));
+        Ok(())
+    }
+
+    fn detach(&mut self) -> Result<(), Error> {
+        self.calls.push(MockCall::Detach);
+        self.event_handler = None;
+        Ok(())
+    }
+
+    fn set_metadata(&mut self, metadata: MediaMetadata) -> Result<(), Error> {
+        self.calls.push(MockCall::SetMetadata(metadata.into()));
+        Ok(())
+    }
+
+    fn set_playback(&mut self, playback: MediaPlayback) -> Result<(), Error> {
+        self.calls.push(MockCall::SetPlayback(playback));
+        Ok(())
+    }
+
+    fn set_volume(&mut self, volume: f64) -> Result<(), Error> {
+        self.calls.push(MockCall::SetVolume(volume));
+        Ok(())
+    }
+
+    fn set_button_enabled(&mut self, button: MediaButton, enabled: bool) -> Result<(), Error> {
+        self.calls.push(MockCall::SetButtonEnabled(button, enabled));
+        Ok(())
+    }
+}
@@ -1,16 +1,234 @@
-use std::ffi::c_void;
+use std::{ffi::c_void, time::Duration};
 
 /// OS-specific configuration needed to create media controls.
 #[derive(Debug)]
 pub struct PlatformConfig<'a> {
-    /// The name to be displayed to the user. (*Required on Linux*)
+    /// The name to be displayed to the user. SMTC on Windows has no such name, and shows the
+    /// app of the [`PlatformConfig::app_id`] instead. (*Required on Linux*)
     pub display_name: &'a str,
     /// Should follow [the D-Bus spec](https://dbus.freedesktop.org/doc/dbus-specification.html#message-protocol-names-bus). (*Required on Linux*)
     pub dbus_name: &'a str,
-    /// An HWND. (*Required on Windows*)
+    /// An HWND. The window must outlive the [`crate::MediaControls`]: creating them fails
+    /// with `Error::InvalidWindowHandle` if it's unset or not a window. (*Required on Windows*)
     pub hwnd: Option<*mut c_void>,
     /// The AppUserModelId for Windows. This is displayed as the app name in SMTC.
     /// If not set, Windows will show "Unknown app". (*Optional, Windows only*)
     /// Example: "com.example.myapp"
     pub app_id: Option<&'a str>,
+    /// Apply volume changes requested by clients immediately and echo the new value
+    /// back to them, instead of waiting for [`MediaControls::set_volume`](crate::MediaControls::set_volume).
+    /// The volume is clamped to be non-negative, as the MPRIS spec requires. (*Optional, Linux only*)
+    pub echo_volume: bool,
+    /// The name of a custom D-Bus interface exposing a settable `Rating` property (0.0-1.0),
+    /// e.g. "org.example.Rating". Changes from clients are delivered as
+    /// [`MediaControlEvent::SetRating`](crate::MediaControlEvent::SetRating), and values out
+    /// of that range are rejected. If not set, no such interface is registered.
+    /// (*Optional, Linux D-Bus backend only*)
+    pub rating_interface: Option<&'a str>,
+    /// Additional object paths to register the MPRIS interfaces on, besides the standard
+    /// `/org/mpris/MediaPlayer2`, e.g. a legacy path older clients still query. Property
+    /// changes are announced on every path. (*Optional, Linux D-Bus backend only*)
+    pub dbus_path_aliases: &'a [&'a str],
+    /// Only publish what's playing, without accepting any control: every `Can*` property
+    /// is false, the methods called by clients have no effect and no event is delivered.
+    /// (*Optional, Linux only*)
+    pub read_only: bool,
+    /// When to emit the MPRIS `Seeked` signal for the progress passed to
+    /// [`MediaControls::set_playback`](crate::MediaControls::set_playback).
+    /// (*Optional, Linux only*)
+    pub seeked_emission: SeekedEmission,
+    /// Also emit the MPRIS `Seeked` signal with the current position at this interval
+    /// while playing, for clients that never read the `Position` property and only follow
+    /// `Seeked`. This bends the MPRIS spec, where `Seeked` marks discontinuities, so other
+    /// clients may e.g. restart an animation on every emission. (*Optional, Linux only*)
+    pub seeked_interval: Option<Duration>,
+    /// The longest the MPRIS service sleeps while nothing happens. It wakes up right away
+    /// for changes and method calls, and in time for timed changes such as the periodic
+    /// `Seeked`, so this only needs lowering if the clock set with
+    /// [`MediaControls::set_clock`](crate::MediaControls::set_clock) jumps, like a manual
+    /// clock in tests. Raised to at least 1ms, so that the service doesn't spin.
+    /// (*Linux only*)
+    pub poll_interval: Duration,
+    /// Register a `com.souvlaki.Info` interface with the read-only `Version` (of this crate)
+    /// and `DisplayName` properties, so that diagnostic tools can tell which players use
+    /// souvlaki. (*Optional, Linux only*)
+    pub serve_version_info: bool,
+    /// How the MPRIS bus name is requested. (*Optional, Linux D-Bus backend only*)
+    pub dbus_name_flags: BusNameFlags,
+    /// If the MPRIS bus name is already owned, e.g. by another instance of the player,
+    /// request it again with the `.instance<pid>` suffix MPRIS recommends, instead of
+    /// failing to attach. (*Optional, Linux only*)
+    pub dbus_name_pid_suffix: bool,
+    /// The bus to serve MPRIS on. (*Optional, Linux only*)
+    pub bus: BusType,
+    /// Connect to the bus again if the connection is lost, e.g. when the session bus
+    /// restarts, instead of stopping the service. Reconnecting is retried with a growing
+    /// delay of up to 30 seconds, and the application is told with
+    /// [`MediaControlEvent::ConnectionLost`](crate::MediaControlEvent::ConnectionLost) and
+    /// [`MediaControlEvent::Reconnected`](crate::MediaControlEvent::Reconnected). Not for
+    /// connections passed to `MediaControls::attach_with_connection`, nor
+    /// `MediaControls::attach_polled`. (*Optional, Linux D-Bus backend only*)
+    pub reconnect: bool,
+    /// Round the duration passed to
+    /// [`MediaControls::set_metadata`](crate::MediaControls::set_metadata) to the nearest
+    /// second, for durations that only have second granularity to begin with.
+    /// (*Optional, Linux only*)
+    pub round_duration_to_seconds: bool,
+    /// Clamp the position served to clients to the duration of the current media item,
+    /// for players that may report a progress past the end, e.g. around track changes.
+    /// (*Optional, Linux only*)
+    pub clamp_position: bool,
+    /// Reset the position to the start and emit `Seeked` when the metadata of a new media
+    /// item, i.e. with another track id, is set, so that clients move their scrubber back
+    /// even if the playback status doesn't change. Off for applications reporting the
+    /// position of the new item themselves. (*Optional, Linux only*)
+    pub seeked_on_track_change: bool,
+    /// The volume served until [`MediaControls::set_volume`](crate::MediaControls::set_volume)
+    /// is called, e.g. a volume restored on launch. Clamped to 0.0-1.0. (*Linux only*)
+    pub initial_volume: f64,
+    /// The lowest playback rate clients may set, served as the MPRIS `MinimumRate`
+    /// property. At most 1.0. (*Linux only*)
+    pub minimum_rate: f64,
+    /// The highest playback rate clients may set, served as the MPRIS `MaximumRate`
+    /// property. At least 1.0. (*Linux only*)
+    pub maximum_rate: f64,
+    /// The URI schemes the player can open, e.g. `file` or `https`, served as the MPRIS
+    /// `SupportedUriSchemes` property for clients calling `OpenUri`, which is delivered as
+    /// [`MediaControlEvent::OpenUri`](crate::MediaControlEvent::OpenUri).
+    /// (*Optional, Linux only*)
+    pub supported_uri_schemes: &'a [&'a str],
+    /// The MIME types the player can open, e.g. `audio/mpeg`, served as the MPRIS
+    /// `SupportedMimeTypes` property. (*Optional, Linux only*)
+    pub supported_mime_types: &'a [&'a str],
+    /// Whether clients may bring the player's user interface to the front, served as the
+    /// MPRIS `CanRaise` property. Requests are delivered as
+    /// [`MediaControlEvent::Raise`](crate::MediaControlEvent::Raise), and ignored if not set.
+    /// (*Linux only*)
+    pub can_raise: bool,
+    /// Whether clients may shut the player down, served as the MPRIS `CanQuit` property.
+    /// Requests are delivered as [`MediaControlEvent::Quit`](crate::MediaControlEvent::Quit),
+    /// and ignored if not set. (*Linux only*)
+    pub can_quit: bool,
+    /// Whether clients may toggle fullscreen, e.g. for a video player, served as the MPRIS
+    /// `CanSetFullscreen` property. Requests are delivered as
+    /// [`MediaControlEvent::SetFullscreen`](crate::MediaControlEvent::SetFullscreen), and
+    /// ignored if not set. (*Linux only*)
+    pub can_set_fullscreen: bool,
+    /// The name of the player's `.desktop` file, without the extension, e.g. "my_player",
+    /// served as the MPRIS `DesktopEntry` property so that shells show the player's icon.
+    /// If not set, the property is omitted. (*Optional, Linux only*)
+    pub desktop_entry: Option<&'a str>,
+    /// Don't connect to the bus at all: attaching and the setters succeed without doing
+    /// anything, and no event is ever delivered. Useful to run an application's tests
+    /// without a D-Bus session bus. The served state can't be read back, as if detached.
+    /// (*Optional, Linux only*)
+    pub dummy: bool,
+}
+
+impl Default for PlatformConfig<'_> {
+    /// Placeholder names, to be replaced by the player's own, with every optional
+    /// feature disabled and the controls served without restrictions.
+    fn default() -> Self {
+        Self {
+            display_name: "Souvlaki Player",
+            dbus_name: "souvlaki_player",
+            hwnd: None,
+            app_id: None,
+            echo_volume: false,
+            rating_interface: None,
+            dbus_path_aliases: &[],
+            read_only: false,
+            seeked_emission: SeekedEmission::default(),
+            seeked_interval: None,
+            poll_interval: Duration::from_secs(1),
+            serve_version_info: false,
+            dbus_name_flags: BusNameFlags::default(),
+            dbus_name_pid_suffix: false,
+            bus: BusType::default(),
+            reconnect: false,
+            round_duration_to_seconds: false,
+            clamp_position: false,
+            seeked_on_track_change: false,
+            initial_volume: 1.0,
+            minimum_rate: 1.0,
+            maximum_rate: 1.0,
+            supported_uri_schemes: &[],
+            supported_mime_types: &[],
+            can_raise: true,
+            can_quit: true,
+            can_set_fullscreen: false,
+            desktop_entry: None,
+            dummy: false,
+        }
+    }
+}
+
+/// The flags the MPRIS bus name is requested with, see
+/// [the D-Bus spec](https://dbus.freedesktop.org/doc/dbus-specification.html#bus-messages-request-name).
+///
+/// The default takes the name over from an owner allowing replacement, and otherwise
+/// waits in the queue for the name until the owner releases it. MPRIS doesn't require
+/// any flags, but recommends appending an instance suffix to the name, e.g.
+/// `my_player.instance1234`, when several instances may run at once.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BusNameFlags {
+    /// Allow another connection to take the name over from this one.
+    pub allow_replacement: bool,
+    /// Take the name over if the current owner allows replacement.
+    pub replace_existing: bool,
+    /// Fail instead of waiting in the queue if the name can't be taken right away.
+    pub do_not_queue: bool,
+}
+
+impl Default for BusNameFlags {
+    fn default() -> Self {
+        Self {
+            allow_replacement: false,
+            replace_existing: true,
+            do_not_queue: false,
+        }
+    }
+}
+
+/// The D-Bus bus the MPRIS service connects to.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub enum BusType {
+    /// The bus of the user's session, where desktop shells look for players.
+    #[default]
+    Session,
+    /// The system-wide bus, e.g. for a player running as a system service in a kiosk or
+    /// embedded setup. Its default policy forbids owning names and sending messages to
+    /// them, so a policy file such as `/etc/dbus-1/system.d/my_player.conf` must allow the
+    /// player's user to own `org.mpris.MediaPlayer2.my_player`, and clients to send to it:
+    ///
+    /// ```xml
+    /// <busconfig>
+    ///   <policy user="my_player">
+    ///     <allow own="org.mpris.MediaPlayer2.my_player"/>
+    ///   </policy>
+    ///   <policy context="default">
+    ///     <allow send_destination="org.mpris.MediaPlayer2.my_player"/>
+    ///   </policy>
+    /// </busconfig>
+    /// ```
+    System,
+}
+
+/// When to emit the MPRIS `Seeked` signal for the progress passed with a playback status.
+///
+/// Clients only read the position when the playback status changes or when `Seeked` is
+/// emitted, and extrapolate it in between. Without `Seeked`, their seekbar may drift
+/// from the actual position; on the other hand, some clients restart animations or
+/// show a notification on every `Seeked`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub enum SeekedEmission {
+    /// Never emit `Seeked` for the progress passed with a playback status. Clients only
+    /// pick up the new position along with a change of the playback status.
+    #[default]
+    Never,
+    /// Emit `Seeked` when the progress differs from the position clients extrapolate by
+    /// more than the given threshold, e.g. after seeking or when playback stalled.
+    OnDiscontinuity(Duration),
+    /// Emit `Seeked` whenever a progress is passed, so that clients always jump to it.
+    Always,
 }
@@ -0,0 +1,34 @@
+use tokio::sync::mpsc;
+
+use crate::{Error, MediaControlEvent, MediaControls};
+
+/// The media control events of [`MediaControls::attach_async`], to be awaited in an async
+/// runtime. Ends once the media controls are detached or dropped.
+#[derive(Debug)]
+pub struct EventStream(mpsc::UnboundedReceiver<MediaControlEvent>);
+
+impl EventStream {
+    /// Wait for the next event, or `None` once the media controls are detached.
+    pub async fn recv(&mut self) -> Option<MediaControlEvent> {
+        self.0.recv().await
+    }
+
+    /// Get the underlying channel, e.g. to wrap it in a `tokio_stream::wrappers` stream.
+    pub fn into_inner(self) -> mpsc::UnboundedReceiver<MediaControlEvent> {
+        self.0
+    }
+}
+
+impl MediaControls {
+    /// Attach the media controls, receiving the events through an [`EventStream`] instead
+    /// of a handler. The service still runs on its own thread, and the events are
+    /// forwarded to the stream, so they can be awaited from any runtime.
+    /// (Only available with the `tokio` feature)
+    pub fn attach_async(&mut self) -> Result<EventStream, Error> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.attach(move |event| {
+            sender.send(event).ok();
+        })?;
+        Ok(EventStream(receiver))
+    }
+}
@@ -1,27 +1,78 @@
 #![doc = include_str!("../README.md")]
 
+mod backend;
+mod clock;
 mod config;
+#[cfg(feature = "tokio")]
+mod event_stream;
 mod platform;
+mod track_id;
 
-use std::{fmt::Debug, time::Duration};
+use std::{collections::HashMap, fmt::Debug, sync::mpsc, time::Duration};
 
+pub use backend::*;
+pub use clock::*;
 pub use config::*;
+#[cfg(feature = "tokio")]
+pub use event_stream::*;
 pub use platform::{Error, MediaControls};
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+))]
+pub use platform::{
+    MediaControlsHandle, MediaMetadataBuilder, OwnedMetadata, StopHandle, MPRIS_OBJECT_PATH,
+};
+pub use track_id::*;
 
 /// The status of media playback.
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MediaPlayback {
     Stopped,
     Paused { progress: Option<MediaPosition> },
     Playing { progress: Option<MediaPosition> },
 }
 
+impl MediaPlayback {
+    /// Playing, at `progress` into the media item.
+    pub fn playing(progress: Duration) -> Self {
+        MediaPlayback::Playing {
+            progress: Some(MediaPosition(progress)),
+        }
+    }
+
+    /// Paused, at `progress` into the media item.
+    pub fn paused(progress: Duration) -> Self {
+        MediaPlayback::Paused {
+            progress: Some(MediaPosition(progress)),
+        }
+    }
+}
+
 /// The metadata of a media item.
 #[derive(Clone, PartialEq, Eq, Debug, Default)]
 pub struct MediaMetadata<'a> {
     pub title: Option<&'a str>,
     pub album: Option<&'a str>,
     pub artist: Option<&'a str>,
+    /// Every credited artist, in order. If set and non-empty, this is used instead of
+    /// `artist`. On Windows and macOS, which show a single artist, they are joined with ", ".
+    pub artists: Option<Vec<&'a str>>,
+    /// The artists credited for the whole album, e.g. "Various Artists" for a compilation.
+    /// Omitted when unset, rather than falling back to the track's artists. On Windows and
+    /// macOS, they are joined with ", ".
+    pub album_artists: Option<Vec<&'a str>>,
+    /// The number of the track on its album or disc, starting at 1. Ignored on macOS.
+    pub track_number: Option<u32>,
+    /// The number of the disc the track is on, starting at 1. Only served on MPRIS.
+    pub disc_number: Option<u32>,
+    /// The genres of the media item. Ignored on macOS.
+    pub genre: Option<Vec<&'a str>>,
+    /// When the media item was created or published, e.g. the date of a podcast episode, as
+    /// an ISO 8601 date and time such as "2024-03-01T08:00:00Z". Passed through as it is, and
+    /// only served on MPRIS, as `xesam:contentCreated`.
+    pub content_created: Option<&'a str>,
     /// Very platform specific. As of now, Souvlaki leaves it up to the user to change the URL depending on the platform.
     ///
     /// For Linux, we follow the MPRIS specification, which actually doesn't say much cover art apart from what's in [here](https://www.freedesktop.org/wiki/Specifications/mpris-spec/metadata/#mpris:arturl). It only says that local files should start with `file://` and that it should be an UTF-8 string, which is enforced by Rust. Maybe you can look in the source code of desktop managers such as GNOME or KDE, since these read the field to display it on their media player controls.
@@ -30,10 +81,205 @@ pub struct MediaMetadata<'a> {
     ///
     /// For MacOS, you can look into [these lines](https://github.com/Sinono3/souvlaki/blob/384539fe83e8bf5c966192ba28e9405e3253619b/src/platform/macos/mod.rs#L131-L137) of the implementation. These lines refer to creating an [MPMediaItemArtwork](https://developer.apple.com/documentation/mediaplayer/mpmediaitemartwork) object.
     pub cover_url: Option<&'a str>,
+    /// The cover art as encoded image bytes, for apps having it in memory rather than at a
+    /// URL. Ignored if `cover_url` is set.
+    ///
+    /// On MPRIS, the bytes are written to a file in `$XDG_RUNTIME_DIR`, or the temporary
+    /// directory if unset, which is served as `mpris:artUrl` and removed when the metadata is
+    /// set again or the media controls are dropped. On Windows, they're passed to the
+    /// thumbnail in memory, and on macOS, they're written with [`cover_file_url`].
+    pub cover_bytes: Option<(ImageFormat, &'a [u8])>,
     pub duration: Option<Duration>,
+    /// Whether the media item is a live stream, such as internet radio, which has no
+    /// duration and can't be seeked. The duration is then ignored: on MPRIS, `mpris:length`
+    /// is omitted and `CanSeek` is false, on Windows, the timeline is cleared and on macOS,
+    /// the item is marked as a live stream.
+    pub live: bool,
+    /// The id of the media item, served as `mpris:trackid` on MPRIS, where clients pass it
+    /// back when setting the position so that stale requests are ignored. If not set, a new
+    /// id such as `/org/souvlaki/track/1` is generated every time the metadata is set, and
+    /// [`TrackId::no_track`] is used for empty metadata. Ignored on other platforms.
+    pub track_id: Option<&'a TrackId>,
+    /// The chapters of the media item, such as those of an audiobook or podcast, as their
+    /// offsets from the start and titles. On MPRIS, they're served under the custom
+    /// `com.souvlaki:chapters` metadata key as an array of offsets in microseconds and
+    /// titles. Ignored on other platforms.
+    pub chapters: Option<Vec<(Duration, &'a str)>>,
+    /// Additional metadata keys, e.g. `xesam:comment` or a key of the player's own such as
+    /// `org.example:streamId`, for what the other fields don't cover. On MPRIS, they're
+    /// added to the served metadata, replacing the keys set from the other fields. Ignored on
+    /// other platforms.
+    pub extra: Option<HashMap<&'a str, MetadataValue>>,
+}
+
+impl MediaMetadata<'_> {
+    /// The artists as a single string, for platforms showing only one.
+    #[cfg(any(target_os = "windows", target_os = "macos", target_os = "ios"))]
+    fn joined_artists(&self) -> Option<String> {
+        match &self.artists {
+            Some(artists) if !artists.is_empty() => Some(artists.join(", ")),
+            _ => self.artist.map(str::to_owned),
+        }
+    }
+}
+
+/// The encoding of cover art bytes, see [`MediaMetadata::cover_bytes`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+}
+
+impl ImageFormat {
+    /// The MIME type of the format, e.g. to be passed to [`cover_data_url`].
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+        }
+    }
+
+    /// The file extension of the format, e.g. to be passed to [`cover_file_url`].
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+        }
+    }
+}
+
+/// The value of an additional metadata key, see [`MediaMetadata::extra`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MetadataValue {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Strings(Vec<String>),
+}
+
+// Floats are compared bitwise, so that metadata can still be compared for equality.
+impl PartialEq for MetadataValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::String(a), Self::String(b)) => a == b,
+            (Self::Integer(a), Self::Integer(b)) => a == b,
+            (Self::Float(a), Self::Float(b)) => a.to_bits() == b.to_bits(),
+            (Self::Strings(a), Self::Strings(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for MetadataValue {}
+
+/// Encode image bytes as a `data:` URL, to be used as [`MediaMetadata::cover_url`].
+///
+/// This avoids writing the cover art to a temporary file when it's only available in memory,
+/// as long as the platform and clients accept data URLs.
+///
+/// **NOTE**: On MPRIS the whole URL is sent over D-Bus, where messages are limited to 128 MiB
+/// by the specification and possibly less by the bus daemon. URLs too large for
+/// `MediaControls::max_message_size` are served from a temporary file instead. Metadata is
+/// also resent on every change, so prefer small, compressed images.
+pub fn cover_data_url(bytes: &[u8], mime_type: &str) -> String {
+    use base64::Engine;
+
+    format!(
+        "data:{};base64,{}",
+        mime_type,
+        base64::engine::general_purpose::STANDARD.encode(bytes)
+    )
+}
+
+/// Write image bytes to a file in the temporary directory, and return its `file://` URL to
+/// be used as [`MediaMetadata::cover_url`].
+///
+/// Unlike [`cover_data_url`], this works on every platform and keeps D-Bus messages small.
+/// Files are named after a hash of the bytes, so the same image, e.g. the cover of every
+/// track of an album, is only written once. Files are left in place to be reused.
+pub fn cover_file_url(bytes: &[u8], extension: &str) -> std::io::Result<String> {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    let path = std::env::temp_dir().join(format!(
+        "souvlaki-cover-{:016x}.{}",
+        hasher.finish(),
+        extension
+    ));
+
+    let is_cached = std::fs::metadata(&path)
+        .map(|metadata| metadata.len() == bytes.len() as u64)
+        .unwrap_or(false);
+    if !is_cached {
+        std::fs::write(&path, bytes)?;
+    }
+
+    Ok(format!("file://{}", path.display()))
+}
+
+/// Download the cover art at an `http://` or `https://` URL, to be passed to
+/// `MediaControls::set_cover_fetcher`. Returns `None` for other URLs, on errors, or if the
+/// download takes longer than 10 seconds or the image is larger than 10 MiB. This is the
+/// default fetcher on Windows. (Only available with the `http_cover` feature)
+#[cfg(feature = "http_cover")]
+pub fn fetch_http_cover(url: &str) -> Option<Vec<u8>> {
+    use std::io::Read;
+
+    const MAX_COVER_SIZE: u64 = 10 * 1024 * 1024;
+
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return None;
+    }
+    let response = ureq::get(url)
+        .timeout(Duration::from_secs(10))
+        .call()
+        .ok()?;
+    let mut bytes = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_COVER_SIZE + 1)
+        .read_to_end(&mut bytes)
+        .ok()?;
+    (bytes.len() as u64 <= MAX_COVER_SIZE).then_some(bytes)
+}
+
+/// Fetch the cover art at `url` with a fetcher set with `MediaControls::set_cover_fetcher`,
+/// and return the `file://` URL of the bytes written with [`cover_file_url`].
+#[cfg(any(target_os = "windows", target_os = "macos", target_os = "ios"))]
+fn fetch_cover_file_url(fetcher: &dyn Fn(&str) -> Option<Vec<u8>>, url: &str) -> Option<String> {
+    let bytes = fetcher(url)?;
+    // The image format is sniffed from the contents anyway, so this is only a hint.
+    let name = url.split(['?', '#']).next()?.rsplit('/').next()?;
+    let extension = match name.rsplit_once('.') {
+        Some((_, extension))
+            if (1..=4).contains(&extension.len())
+                && extension.chars().all(|c| c.is_ascii_alphanumeric()) =>
+        {
+            extension
+        }
+        _ => "img",
+    };
+    cover_file_url(&bytes, extension).ok()
 }
 
 /// Events sent by the OS media controls.
+///
+/// The same events are delivered on every platform, but each platform only sends those it
+/// has an equivalent for, so a handler matching every variant works everywhere:
+///
+/// | Event | MPRIS | Windows | macOS |
+/// |-------|-------|---------|-------|
+/// | `Play`, `Pause`, `Next`, `Previous`, `Stop`, `SetPosition` | yes | yes | yes |
+/// | `Toggle` | yes | no | yes |
+/// | `Seek` | no | yes | no |
+/// | `SeekBy`, `SetVolume`, `OpenUri`, `SetRate`, `SetLoopStatus`, `SetShuffle` | yes | no | no |
+/// | `Raise`, `Quit`, `SetFullscreen` | yes | no | no |
+/// | `SetRating`, `ConnectionLost`, `Reconnected` | D-Bus backend only | no | no |
+/// | `ToggleShuffle`, `CycleLoopStatus` | D-Bus backend only | no | no |
+///
+/// Events carrying a float, such as the volume, can only be compared with `PartialEq`.
 #[derive(Clone, PartialEq, Debug)]
 pub enum MediaControlEvent {
     Play,
@@ -41,15 +287,26 @@ pub enum MediaControlEvent {
     Toggle,
     Next,
     Previous,
+    /// Stop playback. Whether the media item stays loaded is up to the application,
+    /// which reports it with `MediaControls::set_playback_stopped`.
     Stop,
 
     /// Seek forward or backward by an undetermined amount.
     Seek(SeekDirection),
-    /// Seek forward or backward by a certain amount.
+    /// Seek forward or backward by a certain amount, relative to the current position.
+    ///
+    /// On MPRIS, this is the offset of a `Seek` call, unlike the absolute position of
+    /// [`MediaControlEvent::SetPosition`]. The offset is delivered as-is, even when seeking
+    /// backward would go before the start, so clamping it is up to the application.
     SeekBy(SeekDirection, Duration),
     /// Set the position/progress of the currently playing media item.
+    ///
+    /// On MPRIS, this is the absolute position of a `SetPosition` call. As the specification
+    /// requires, calls for another track id than the current `mpris:trackid` are ignored as
+    /// stale, as are positions before the start or past the duration, rather than clamped.
     SetPosition(MediaPosition),
-    /// Sets the volume. The value is intended to be from 0.0 to 1.0.
+    /// Sets the volume. The value is intended to be from 0.0 to 1.0, or in
+    /// the scale set with `MediaControls::set_volume_scale`.
     /// But other values are also accepted. **It is up to the user to
     /// set constraints on this value.**
     /// **NOTE**: If the volume event was received and correctly handled,
@@ -58,26 +315,138 @@ pub enum MediaControlEvent {
     SetVolume(f64),
     /// Open the URI in the media player.
     OpenUri(String),
+    /// Set the playback rate, e.g. 1.5 to play 50% faster, already clamped to the rates
+    /// configured with `PlatformConfig::minimum_rate` and `PlatformConfig::maximum_rate`.
+    /// The application confirms the change by calling `MediaControls::set_rate`.
+    SetRate(f64),
+    /// Set the repeat mode. The application confirms the change by calling
+    /// `MediaControls::set_loop_status`.
+    SetLoopStatus(LoopStatus),
+    /// Enable or disable shuffling. The application confirms the change by calling
+    /// `MediaControls::set_shuffle`.
+    SetShuffle(bool),
+    /// Toggle shuffling, for clients asking for it without an explicit value. MPRIS clients
+    /// set `Shuffle` to a value, delivered as [`MediaControlEvent::SetShuffle`], so this is
+    /// only sent when returned by the callback of `MediaControls::on_property_set`.
+    ToggleShuffle,
+    /// Switch to the next repeat mode, e.g. with [`LoopStatus::next`], for clients asking for
+    /// it without an explicit value. Like [`MediaControlEvent::ToggleShuffle`], this is only
+    /// sent when returned by the callback of `MediaControls::on_property_set`.
+    CycleLoopStatus,
+    /// Set the rating of the current media item, from 0.0 to 1.0.
+    /// Only sent when [`PlatformConfig::rating_interface`] is set.
+    SetRating(f64),
 
     /// Bring the media player's user interface to the front using any appropriate mechanism available.
     Raise,
     /// Shut down the media player.
     Quit,
+    /// Enter or leave fullscreen. Only sent when `PlatformConfig::can_set_fullscreen` is
+    /// set. The application confirms the change by calling `MediaControls::set_fullscreen`.
+    SetFullscreen(bool),
+
+    /// The connection to the bus was lost, so clients can't see the player until
+    /// [`MediaControlEvent::Reconnected`]. Only sent when `PlatformConfig::reconnect` is set,
+    /// but also to read-only or suspended players, unlike the events from clients.
+    ConnectionLost,
+    /// The connection to the bus was established again after
+    /// [`MediaControlEvent::ConnectionLost`], serving the current state.
+    Reconnected,
 }
 
 /// An instant in a media item.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MediaPosition(pub Duration);
 
+impl MediaPosition {
+    /// The position `millis` milliseconds into the media item.
+    pub fn from_millis(millis: u64) -> Self {
+        MediaPosition(Duration::from_millis(millis))
+    }
+
+    /// The position `micros` microseconds into the media item, as used by MPRIS.
+    /// Negative values are clamped to the start.
+    pub fn from_micros(micros: i64) -> Self {
+        MediaPosition(Duration::from_micros(u64::try_from(micros).unwrap_or(0)))
+    }
+
+    /// The position in whole milliseconds, saturating at `u64::MAX`.
+    pub fn as_millis(self) -> u64 {
+        u64::try_from(self.0.as_millis()).unwrap_or(u64::MAX)
+    }
+
+    /// The position in whole microseconds, saturating at `i64::MAX` like on MPRIS.
+    pub fn as_micros(self) -> i64 {
+        i64::try_from(self.0.as_micros()).unwrap_or(i64::MAX)
+    }
+}
+
 /// The direction to seek in.
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum SeekDirection {
     Forward,
     Backward,
 }
 
+/// The scale of the volume delivered in [`MediaControlEvent::SetVolume`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub enum VolumeScale {
+    /// Linear from 0.0 to 1.0, like the MPRIS `Volume` property.
+    #[default]
+    Linear,
+    /// Decibels relative to full volume: 0.0 dB is a linear volume of 1.0, -6.02 dB is
+    /// 0.5 and a linear volume of 0.0 is negative infinity.
+    Decibels,
+}
+
+impl VolumeScale {
+    /// Convert a linear volume to this scale. In decibels, negative volumes are
+    /// treated as 0.0, as the MPRIS specification requires.
+    pub fn convert(self, linear: f64) -> f64 {
+        match self {
+            VolumeScale::Linear => linear,
+            VolumeScale::Decibels => 20.0 * linear.max(0.0).log10(),
+        }
+    }
+
+    /// Convert a volume in this scale to a linear volume.
+    pub fn to_linear(self, volume: f64) -> f64 {
+        match self {
+            VolumeScale::Linear => volume,
+            VolumeScale::Decibels => 10f64.powf(volume / 20.0),
+        }
+    }
+}
+
+/// The repeat mode of the playback, like the MPRIS `LoopStatus` property.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LoopStatus {
+    /// Playback stops at the end of the playlist.
+    #[default]
+    None,
+    /// The current track is repeated.
+    Track,
+    /// The playlist is repeated.
+    Playlist,
+}
+
+impl LoopStatus {
+    /// The repeat mode after this one, in the order a repeat button usually cycles
+    /// through them: none, the playlist, then the track.
+    pub fn next(self) -> Self {
+        match self {
+            LoopStatus::None => LoopStatus::Playlist,
+            LoopStatus::Playlist => LoopStatus::Track,
+            LoopStatus::Track => LoopStatus::None,
+        }
+    }
+}
+
 /// Media control buttons that can be enabled or disabled.
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MediaButton {
     Play,
     Pause,
@@ -87,6 +456,114 @@ pub enum MediaButton {
     Seek,
 }
 
+/// Which media control buttons are enabled, set together with
+/// [`MediaControls::set_capabilities`] and read together with `MediaControls::capabilities`
+/// on MPRIS. Every button is enabled by default.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Capabilities {
+    pub can_play: bool,
+    pub can_pause: bool,
+    /// There is no `CanStop` property in MPRIS, so this is ignored there.
+    pub can_stop: bool,
+    pub can_go_next: bool,
+    pub can_go_previous: bool,
+    /// Read back as false on MPRIS while the media item is live, which can't be seeked.
+    pub can_seek: bool,
+    /// Whether clients can control playback at all, as set with
+    /// `MediaControls::set_can_control`. Only served on MPRIS, ignored elsewhere.
+    pub can_control: bool,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            can_play: true,
+            can_pause: true,
+            can_stop: true,
+            can_go_next: true,
+            can_go_previous: true,
+            can_seek: true,
+            can_control: true,
+        }
+    }
+}
+
+impl Capabilities {
+    fn buttons(self) -> [(MediaButton, bool); 6] {
+        [
+            (MediaButton::Play, self.can_play),
+            (MediaButton::Pause, self.can_pause),
+            (MediaButton::Stop, self.can_stop),
+            (MediaButton::Next, self.can_go_next),
+            (MediaButton::Previous, self.can_go_previous),
+            (MediaButton::Seek, self.can_seek),
+        ]
+    }
+}
+
+impl MediaControls {
+    /// Create media controls with the specified config and attach them to a handler, for
+    /// the common case of a single handler for the whole life of the controls.
+    pub fn start<F>(config: PlatformConfig, event_handler: F) -> Result<Self, Error>
+    where
+        F: Fn(MediaControlEvent) + Send + 'static,
+    {
+        let mut controls = Self::new(config)?;
+        controls.attach(event_handler)?;
+        Ok(controls)
+    }
+
+    /// Attach the media control events to a channel instead of a handler, e.g. to receive
+    /// them in an existing event loop. Events are dropped once the receiver is dropped.
+    pub fn attach_channel(&mut self) -> Result<mpsc::Receiver<MediaControlEvent>, Error> {
+        let (tx, rx) = mpsc::channel();
+        self.attach(move |event| {
+            tx.send(event).ok();
+        })?;
+        Ok(rx)
+    }
+
+    /// Enable or disable every media control button at once, as with
+    /// [`MediaControls::set_button_enabled`]. On MPRIS, the changes are sent together
+    /// in a single `PropertiesChanged` signal, so clients never see only some of them.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) -> Result<(), Error> {
+        self.batched(|controls| {
+            for (button, enabled) in capabilities.buttons() {
+                controls.set_button_enabled(button, enabled)?;
+            }
+            #[cfg(all(
+                unix,
+                not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+            ))]
+            controls.set_can_control(capabilities.can_control)?;
+            Ok(())
+        })
+    }
+
+    /// Call `update`, sending the changes it makes together where supported.
+    #[cfg(all(
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    ))]
+    fn batched<F>(&mut self, update: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Self) -> Result<(), Error>,
+    {
+        self.update(update)
+    }
+
+    #[cfg(not(all(
+        unix,
+        not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+    )))]
+    fn batched<F>(&mut self, update: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Self) -> Result<(), Error>,
+    {
+        update(self)
+    }
+}
+
 impl Drop for MediaControls {
     fn drop(&mut self) {
         // Ignores errors if there are any.
@@ -1,4 +1,8 @@
-use crate::{MediaButton, MediaControlEvent, MediaMetadata, MediaPlayback, PlatformConfig};
+use std::time::Duration;
+
+use crate::{
+    MediaButton, MediaControlEvent, MediaMetadata, MediaPlayback, MediaPosition, PlatformConfig,
+};
 
 /// A platform-specific error.
 #[derive(Debug)]
@@ -34,18 +38,91 @@ impl MediaControls {
         Ok(())
     }
 
+    /// Whether an event handler is attached. Always `false`, as events are never emitted.
+    pub fn is_attached(&self) -> bool {
+        false
+    }
+
     /// Set the current playback status.
     pub fn set_playback(&mut self, _playback: MediaPlayback) -> Result<(), Error> {
         Ok(())
     }
 
+    /// Set the playback status to stopped, and clear the metadata too if `clear_metadata` is
+    /// set. Keep the metadata when the media item stays loaded after stopping, so that
+    /// clients keep showing it, and clear it when there's nothing to resume.
+    pub fn set_playback_stopped(&mut self, clear_metadata: bool) -> Result<(), Error> {
+        if clear_metadata {
+            self.set_metadata(MediaMetadata::default())?;
+        }
+        self.set_playback(MediaPlayback::Stopped)
+    }
+
     /// Set the metadata of the currently playing media item.
     pub fn set_metadata(&mut self, _metadata: MediaMetadata) -> Result<(), Error> {
         Ok(())
     }
 
+    /// Set the metadata and the playback status of a new media item at once. Does nothing.
+    pub fn set_now_playing(
+        &mut self,
+        _metadata: MediaMetadata,
+        _playback: MediaPlayback,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Set a function fetching the bytes of the cover art at a URL. Does nothing.
+    pub fn set_cover_fetcher<F>(&mut self, _fetcher: F)
+    where
+        F: Fn(&str) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+    }
+
     /// Enable or disable a specific media control button.
     pub fn set_button_enabled(&mut self, _button: MediaButton, _enabled: bool) -> Result<(), Error> {
         Ok(())
     }
+
+    /// Whether a specific media control button is enabled.
+    pub fn is_button_enabled(&self, _button: MediaButton) -> bool {
+        true
+    }
+
+    /// Report an error, such as a decoding failure or a lost network connection.
+    /// The message is ignored.
+    pub fn set_error(&mut self, _message: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Set the position of the current media item, keeping the playback status.
+    pub fn set_position(&mut self, _position: MediaPosition) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Set the progress of the current media item, keeping the playback status.
+    pub fn set_progress(&mut self, _progress: Duration) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Report that the current track played until its end.
+    pub fn track_ended(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Set the state for when there's nothing to play: playback is stopped and the play,
+    /// pause, next, previous and seek controls are disabled. Enable them again with
+    /// [`MediaControls::set_button_enabled`] once something is loaded.
+    pub fn set_empty_queue(&mut self) -> Result<(), Error> {
+        for button in [
+            MediaButton::Play,
+            MediaButton::Pause,
+            MediaButton::Next,
+            MediaButton::Previous,
+            MediaButton::Seek,
+        ] {
+            self.set_button_enabled(button, false)?;
+        }
+        self.set_playback(MediaPlayback::Stopped)
+    }
 }
@@ -14,7 +14,7 @@ use std::{
 
 use block::ConcreteBlock;
 use cocoa::{
-    base::{id, nil, NO, YES},
+    base::{id, nil, BOOL, NO, YES},
     foundation::{NSInteger, NSString, NSUInteger},
 };
 use core_graphics::geometry::CGSize;
@@ -37,46 +37,151 @@ impl std::fmt::Display for Error {
 impl std::error::Error for Error {}
 
 /// A handle to OS media controls.
-pub struct MediaControls;
+pub struct MediaControls {
+    cover_fetcher: Option<CoverFetcher>,
+    attached: bool,
+}
+
+type CoverFetcher = Arc<dyn Fn(&str) -> Option<Vec<u8>> + Send + Sync>;
 
 impl MediaControls {
     /// Create media controls with the specified config.
     pub fn new(_config: PlatformConfig) -> Result<Self, Error> {
-        Ok(Self)
+        Ok(Self {
+            cover_fetcher: None,
+            attached: false,
+        })
     }
 
     /// Attach the media control events to a handler.
+    ///
+    /// Attaching again replaces the previous handler.
     pub fn attach<F>(&mut self, event_handler: F) -> Result<(), Error>
     where
         F: Fn(MediaControlEvent) + Send + 'static,
     {
+        self.detach()?;
         unsafe { attach_command_handlers(Arc::new(event_handler)) };
+        self.attached = true;
         Ok(())
     }
 
     /// Detach the event handler.
     pub fn detach(&mut self) -> Result<(), Error> {
         unsafe { detach_command_handlers() };
+        self.attached = false;
         Ok(())
     }
 
+    /// Whether an event handler is attached.
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
     /// Set the current playback status.
     pub fn set_playback(&mut self, playback: MediaPlayback) -> Result<(), Error> {
         unsafe { set_playback_status(playback) };
         Ok(())
     }
 
+    /// Set the playback status to stopped, and clear the metadata too if `clear_metadata` is
+    /// set. Keep the metadata when the media item stays loaded after stopping, so that
+    /// clients keep showing it, and clear it when there's nothing to resume.
+    pub fn set_playback_stopped(&mut self, clear_metadata: bool) -> Result<(), Error> {
+        if clear_metadata {
+            self.set_metadata(MediaMetadata::default())?;
+        }
+        self.set_playback(MediaPlayback::Stopped)
+    }
+
     /// Set the metadata of the currently playing media item.
     pub fn set_metadata(&mut self, metadata: MediaMetadata) -> Result<(), Error> {
-        unsafe { set_playback_metadata(metadata) };
+        unsafe { set_playback_metadata(metadata, self.cover_fetcher.clone()) };
         Ok(())
     }
 
+    /// Set the metadata and the playback status of a new media item at once, e.g. when
+    /// changing tracks.
+    pub fn set_now_playing(
+        &mut self,
+        metadata: MediaMetadata,
+        playback: MediaPlayback,
+    ) -> Result<(), Error> {
+        self.set_metadata(metadata)?;
+        self.set_playback(playback)
+    }
+
+    /// Set a function fetching the bytes of the cover art at a URL, e.g. over HTTP, for
+    /// apps that only have a URL of the art. It's called on a background queue for each
+    /// cover URL not starting with `file://` passed to [`MediaControls::set_metadata`],
+    /// and the bytes are written to a temporary file with
+    /// [`cover_file_url`](crate::cover_file_url) to be loaded from there. If it returns
+    /// `None`, the URL is loaded as it is. On MPRIS, URLs are passed straight to clients
+    /// and the fetcher is never called.
+    pub fn set_cover_fetcher<F>(&mut self, fetcher: F)
+    where
+        F: Fn(&str) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.cover_fetcher = Some(Arc::new(fetcher));
+    }
+
     /// Enable or disable a specific media control button.
     pub fn set_button_enabled(&mut self, button: MediaButton, enabled: bool) -> Result<(), Error> {
         unsafe { set_command_enabled(button, enabled) };
         Ok(())
     }
+
+    /// Whether a specific media control button is enabled.
+    pub fn is_button_enabled(&self, button: MediaButton) -> bool {
+        unsafe { is_command_enabled(button) }
+    }
+
+    /// Report an error, such as a decoding failure or a lost network connection. The Now
+    /// Playing info has no error status, so playback is stopped and the message is ignored.
+    pub fn set_error(&mut self, _message: &str) -> Result<(), Error> {
+        self.set_playback(MediaPlayback::Stopped)
+    }
+
+    /// Set the position of the current media item, e.g. after seeking from the application,
+    /// keeping the playback status.
+    pub fn set_position(&mut self, position: MediaPosition) -> Result<(), Error> {
+        unsafe { set_playback_progress(position.0) };
+        Ok(())
+    }
+
+    /// Set the progress of the current media item, e.g. from a timer while playing, keeping
+    /// the playback status. The same as [`MediaControls::set_position`] on this platform.
+    pub fn set_progress(&mut self, progress: Duration) -> Result<(), Error> {
+        self.set_position(MediaPosition(progress))
+    }
+
+    /// Report that the current track played until its end. If its duration is known,
+    /// playback is paused at the end of the track, otherwise it's stopped.
+    pub fn track_ended(&mut self) -> Result<(), Error> {
+        let playback = match unsafe { playback_duration() } {
+            Some(duration) => MediaPlayback::Paused {
+                progress: Some(MediaPosition(duration)),
+            },
+            None => MediaPlayback::Stopped,
+        };
+        self.set_playback(playback)
+    }
+
+    /// Set the state for when there's nothing to play: playback is stopped and the play,
+    /// pause, next, previous and seek controls are disabled. Enable them again with
+    /// [`MediaControls::set_button_enabled`] once something is loaded.
+    pub fn set_empty_queue(&mut self) -> Result<(), Error> {
+        for button in [
+            MediaButton::Play,
+            MediaButton::Pause,
+            MediaButton::Next,
+            MediaButton::Previous,
+            MediaButton::Seek,
+        ] {
+            self.set_button_enabled(button, false)?;
+        }
+        self.set_playback(MediaPlayback::Stopped)
+    }
 }
 
 // MPNowPlayingPlaybackState
@@ -91,9 +196,11 @@ extern "C" {
     static MPMediaItemPropertyTitle: id; // NSString
     static MPMediaItemPropertyArtist: id; // NSString
     static MPMediaItemPropertyAlbumTitle: id; // NSString
+    static MPMediaItemPropertyAlbumArtist: id; // NSString
     static MPMediaItemPropertyArtwork: id; // NSString
     static MPMediaItemPropertyPlaybackDuration: id; // NSString
     static MPNowPlayingInfoPropertyElapsedPlaybackTime: id; // NSString
+    static MPNowPlayingInfoPropertyIsLiveStream: id; // NSString
 }
 
 unsafe fn set_playback_status(playback: MediaPlayback) {
@@ -117,7 +224,7 @@ unsafe fn set_playback_status(playback: MediaPlayback) {
 
 static GLOBAL_METADATA_COUNTER: AtomicUsize = AtomicUsize::new(1);
 
-unsafe fn set_playback_metadata(metadata: MediaMetadata) {
+unsafe fn set_playback_metadata(metadata: MediaMetadata, cover_fetcher: Option<CoverFetcher>) {
     let prev_counter = GLOBAL_METADATA_COUNTER.fetch_add(1, Ordering::SeqCst);
     let media_center: id = msg_send!(class!(MPNowPlayingInfoCenter), defaultCenter);
     let now_playing: id = msg_send!(class!(NSMutableDictionary), dictionary);
@@ -125,21 +232,39 @@ unsafe fn set_playback_metadata(metadata: MediaMetadata) {
         let _: () = msg_send!(now_playing, setObject: ns_string(title)
                                               forKey: MPMediaItemPropertyTitle);
     }
-    if let Some(artist) = metadata.artist {
-        let _: () = msg_send!(now_playing, setObject: ns_string(artist)
+    if let Some(artist) = metadata.joined_artists() {
+        let _: () = msg_send!(now_playing, setObject: ns_string(&artist)
                                               forKey: MPMediaItemPropertyArtist);
     }
     if let Some(album) = metadata.album {
         let _: () = msg_send!(now_playing, setObject: ns_string(album)
                                               forKey: MPMediaItemPropertyAlbumTitle);
     }
-    if let Some(duration) = metadata.duration {
+    if let Some(album_artists) = &metadata.album_artists {
+        let _: () = msg_send!(now_playing, setObject: ns_string(&album_artists.join(", "))
+                                              forKey: MPMediaItemPropertyAlbumArtist);
+    }
+    if metadata.live {
+        let is_live_stream: id = msg_send!(class!(NSNumber), numberWithBool: YES);
+        let _: () = msg_send!(now_playing, setObject: is_live_stream
+                                              forKey: MPNowPlayingInfoPropertyIsLiveStream);
+    } else if let Some(duration) = metadata.duration {
         let _: () = msg_send!(now_playing, setObject: ns_number(duration.as_secs_f64())
                                               forKey: MPMediaItemPropertyPlaybackDuration);
     }
-    if let Some(cover_url) = metadata.cover_url {
-        let cover_url = cover_url.to_owned();
+    let cover_url = match (metadata.cover_url, metadata.cover_bytes) {
+        (Some(cover_url), _) => Some(cover_url.to_owned()),
+        (None, Some((format, bytes))) => crate::cover_file_url(bytes, format.extension()).ok(),
+        (None, None) => None,
+    };
+    if let Some(cover_url) = cover_url {
         Queue::global(QueuePriority::Default).exec_async(move || {
+            let cover_url = match cover_fetcher {
+                Some(fetcher) if !cover_url.starts_with("file://") => {
+                    crate::fetch_cover_file_url(&*fetcher, &cover_url).unwrap_or(cover_url)
+                }
+                _ => cover_url,
+            };
             load_and_set_playback_artwork(cover_url, prev_counter + 1);
         });
     }
@@ -174,6 +299,20 @@ unsafe fn set_playback_progress(progress: Duration) {
     let _: () = msg_send!(media_center, setNowPlayingInfo: now_playing);
 }
 
+unsafe fn playback_duration() -> Option<Duration> {
+    let media_center: id = msg_send!(class!(MPNowPlayingInfoCenter), defaultCenter);
+    let now_playing: id = msg_send!(media_center, nowPlayingInfo);
+    if now_playing == nil {
+        return None;
+    }
+    let duration: id = msg_send!(now_playing, objectForKey: MPMediaItemPropertyPlaybackDuration);
+    if duration == nil {
+        return None;
+    }
+    let seconds: f64 = msg_send!(duration, doubleValue);
+    Some(Duration::from_secs_f64(seconds))
+}
+
 unsafe fn attach_command_handlers(handler: Arc<dyn Fn(MediaControlEvent)>) {
     let command_center: id = msg_send!(class!(MPRemoteCommandCenter), sharedCommandCenter);
 
@@ -216,6 +355,19 @@ unsafe fn attach_command_handlers(handler: Arc<dyn Fn(MediaControlEvent)>) {
     let _: () = msg_send!(cmd, setEnabled: YES);
     let _: () = msg_send!(cmd, addTargetWithHandler: pause_handler);
 
+    // stopCommand
+    let stop_handler = ConcreteBlock::new({
+        let handler = handler.clone();
+        move |_event: id| -> NSInteger {
+            (handler)(MediaControlEvent::Stop);
+            MPRemoteCommandHandlerStatusSuccess
+        }
+    })
+    .copy();
+    let cmd: id = msg_send!(command_center, stopCommand);
+    let _: () = msg_send!(cmd, setEnabled: YES);
+    let _: () = msg_send!(cmd, addTargetWithHandler: stop_handler);
+
     // previousTrackCommand
     let previous_track_handler = ConcreteBlock::new({
         let handler = handler.clone();
@@ -275,6 +427,10 @@ unsafe fn detach_command_handlers() {
     let _: () = msg_send!(cmd, setEnabled: NO);
     let _: () = msg_send!(cmd, removeTarget: nil);
 
+    let cmd: id = msg_send!(command_center, stopCommand);
+    let _: () = msg_send!(cmd, setEnabled: NO);
+    let _: () = msg_send!(cmd, removeTarget: nil);
+
     let cmd: id = msg_send!(command_center, previousTrackCommand);
     let _: () = msg_send!(cmd, setEnabled: NO);
     let _: () = msg_send!(cmd, removeTarget: nil);
@@ -296,14 +452,12 @@ unsafe fn set_command_enabled(button: MediaButton, enabled: bool) {
         MediaButton::Play => {
             let cmd: id = msg_send!(command_center, playCommand);
             let _: () = msg_send!(cmd, setEnabled: enabled_val);
-            // Also update toggle since play/pause are linked
-            let cmd: id = msg_send!(command_center, togglePlayPauseCommand);
-            let _: () = msg_send!(cmd, setEnabled: enabled_val);
         }
         MediaButton::Pause => {
             let cmd: id = msg_send!(command_center, pauseCommand);
             let _: () = msg_send!(cmd, setEnabled: enabled_val);
-            // Also update toggle since play/pause are linked
+            // The toggle follows the pause command, like `PlayPause` follows `CanPause`
+            // on MPRIS.
             let cmd: id = msg_send!(command_center, togglePlayPauseCommand);
             let _: () = msg_send!(cmd, setEnabled: enabled_val);
         }
@@ -326,6 +480,21 @@ unsafe fn set_command_enabled(button: MediaButton, enabled: bool) {
     }
 }
 
+unsafe fn is_command_enabled(button: MediaButton) -> bool {
+    let command_center: id = msg_send!(class!(MPRemoteCommandCenter), sharedCommandCenter);
+
+    let cmd: id = match button {
+        MediaButton::Play => msg_send!(command_center, playCommand),
+        MediaButton::Pause => msg_send!(command_center, pauseCommand),
+        MediaButton::Stop => msg_send!(command_center, stopCommand),
+        MediaButton::Next => msg_send!(command_center, nextTrackCommand),
+        MediaButton::Previous => msg_send!(command_center, previousTrackCommand),
+        MediaButton::Seek => msg_send!(command_center, changePlaybackPositionCommand),
+    };
+    let enabled: BOOL = msg_send!(cmd, isEnabled);
+    enabled == YES
+}
+
 unsafe fn ns_string(value: &str) -> id {
     NSString::alloc(nil).init_str(value)
 }
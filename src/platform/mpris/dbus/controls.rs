@@ -1,57 +1,439 @@
 use dbus::arg::{RefArg, Variant};
+use dbus::blocking::stdintf::org_freedesktop_dbus::RequestNameReply;
 use dbus::blocking::Connection;
 use dbus::channel::{MatchingReceiver, Sender};
 use dbus::ffidisp::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
-use dbus::message::SignalArgs;
+use dbus::message::{MatchRule, SignalArgs};
+use dbus::Message;
 use dbus::Path;
-use std::collections::HashMap;
+use dbus_crossroads::{Crossroads, IfaceToken};
+use std::collections::{HashMap, HashSet};
 use std::convert::From;
-use std::convert::TryInto;
-use std::sync::{mpsc, Arc, Mutex};
+use std::convert::TryFrom;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use super::super::Error;
-use crate::{MediaButton, MediaControlEvent, MediaMetadata, MediaPlayback, PlatformConfig};
+use super::super::{
+    deliver_event, is_seek, is_served_enabled, loop_status_name, round_to_seconds,
+    service_thread_name, to_i32, to_micros, to_owned_strings, validate_bus_name, with_position,
+    Error, MetadataTransform, OpenUriHandler, SetterState, AVAILABLE_RATES_METADATA_KEY,
+    BUS_NAME_PREFIX, CHAPTERS_METADATA_KEY, ERROR_METADATA_KEY, MAX_MESSAGE_SIZE,
+    MPRIS_OBJECT_PATH,
+};
+use super::waking_channel;
+use crate::{
+    BusNameFlags, BusType, Capabilities, Clock, LoopStatus, MediaButton, MediaControlEvent,
+    MediaMetadata, MediaPlayback, MediaPosition, MetadataValue, PlatformConfig, SeekedEmission,
+    SystemClock, TrackId, VolumeScale,
+};
 
 /// A handle to OS media controls.
 pub struct MediaControls {
     thread: Option<ServiceThreadHandle>,
+    /// The service run by [`MediaControls::process_one`] instead of a thread.
+    polled: Option<PolledService>,
     dbus_name: String,
-    friendly_name: String,
+    dbus_name_flags: BusNameFlags,
+    dbus_name_pid_suffix: bool,
+    bus: BusType,
+    /// Whether to connect again if the connection is lost, see [`PlatformConfig::reconnect`].
+    reconnect: bool,
+    /// The well-known name requested for the current service.
+    bus_name: Option<String>,
+    config: ServiceConfig,
+    last_error: Arc<Mutex<Option<Error>>>,
+    client_callbacks: ClientCallbacks,
+    match_handlers: Vec<MatchHandler>,
+    stop_channel: Option<(
+        waking_channel::Sender<InternalEvent>,
+        waking_channel::Receiver<InternalEvent>,
+    )>,
+    /// What the setters keep between calls, shared with the [`MediaControlsHandle`]s.
+    setter_state: Arc<Mutex<SetterState>>,
+    /// The events queued by the setters within [`MediaControls::update`].
+    batch: Option<Vec<InternalEvent>>,
+    /// Whether to do nothing at all, see [`PlatformConfig::dummy`].
+    dummy: bool,
 }
 
+/// A handle to stop the service of [`MediaControls::attach`] or
+/// [`MediaControls::run_blocking`], e.g. from the event handler or another thread.
+#[derive(Clone, Debug)]
+pub struct StopHandle(waking_channel::Sender<InternalEvent>);
+
+impl StopHandle {
+    /// Make the service return from [`MediaControls::run_blocking`], or end the service
+    /// thread of [`MediaControls::attach`]. The controls stay attached until
+    /// [`MediaControls::detach`] is called, which then joins the ended thread.
+    pub fn stop(&self) {
+        // If the service isn't running anymore there's nothing to stop.
+        self.0.send(InternalEvent::Kill).ok();
+    }
+}
+
+/// A handle to set the state of [`MediaControls`] from another thread, e.g. a decoder
+/// thread while the UI thread owns the controls. Clone it for every thread: its setters
+/// take `&self`, so no mutex is needed. Get one with [`MediaControls::handle`].
+#[derive(Clone, Debug)]
+pub struct MediaControlsHandle {
+    /// `None` in [`PlatformConfig::dummy`] mode, where the setters do nothing.
+    event_channel: Option<waking_channel::Sender<InternalEvent>>,
+    setter_state: Arc<Mutex<SetterState>>,
+    round_duration_to_seconds: bool,
+}
+
+impl MediaControlsHandle {
+    /// Set the metadata of the currently playing media item, as with
+    /// [`MediaControls::set_metadata`].
+    pub fn set_metadata(&self, metadata: MediaMetadata) -> Result<(), Error> {
+        let metadata = self.setter_state.lock().unwrap().to_owned_metadata(
+            metadata,
+            false,
+            self.round_duration_to_seconds,
+            Some(MAX_MESSAGE_SIZE),
+        );
+        self.send_internal_event(InternalEvent::ChangeMetadata(metadata))
+    }
+
+    /// Set the current playback status, as with [`MediaControls::set_playback`].
+    pub fn set_playback(&self, playback: MediaPlayback) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangePlayback(playback))
+    }
+
+    /// Set the volume level, as with [`MediaControls::set_volume`].
+    pub fn set_volume(&self, volume: f64) -> Result<(), Error> {
+        if !volume.is_finite() {
+            return Err(Error::InvalidVolume(volume));
+        }
+        self.send_internal_event(InternalEvent::ChangeVolume(volume.max(0.0)))
+    }
+
+    /// Enable or disable a specific media control button, as with
+    /// [`MediaControls::set_button_enabled`].
+    pub fn set_button_enabled(&self, button: MediaButton, enabled: bool) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeButtonEnabled(button, enabled))?;
+        let mut setter_state = self.setter_state.lock().unwrap();
+        setter_state.record_button_enabled(button, enabled);
+        Ok(())
+    }
+
+    fn send_internal_event(&self, event: InternalEvent) -> Result<(), Error> {
+        match &self.event_channel {
+            // The channel is disconnected once the controls are detached.
+            Some(event_channel) => event_channel
+                .send(event)
+                .map_err(|_| Error::ThreadNotRunning),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Settings the service is run with, taken from the [`PlatformConfig`].
+#[derive(Clone, Debug)]
+pub struct ServiceConfig {
+    pub friendly_name: String,
+    pub echo_volume: bool,
+    pub rating_interface: Option<String>,
+    pub path_aliases: Vec<String>,
+    pub read_only: bool,
+    pub volume_scale: VolumeScale,
+    pub seeked_emission: SeekedEmission,
+    pub seeked_interval: Option<Duration>,
+    pub poll_interval: Duration,
+    pub serve_version_info: bool,
+    pub round_duration_to_seconds: bool,
+    pub clamp_position: bool,
+    pub seeked_on_track_change: bool,
+    pub initial_volume: f64,
+    pub minimum_rate: f64,
+    pub maximum_rate: f64,
+    pub supported_uri_schemes: Vec<String>,
+    pub supported_mime_types: Vec<String>,
+    pub can_raise: bool,
+    pub can_quit: bool,
+    pub can_set_fullscreen: bool,
+    pub desktop_entry: Option<String>,
+    pub metadata_transform: Option<MetadataTransform>,
+    pub open_uri_handler: Option<OpenUriHandler>,
+    pub interfaces_hook: Option<InterfacesHook>,
+    pub clock: Arc<dyn Clock>,
+    /// Shared with the service, see [`MediaControls::suspend`].
+    pub suspended: Arc<AtomicBool>,
+}
+
+/// A function registering more interfaces on the MPRIS object, see
+/// [`MediaControls::register_interfaces`].
+#[derive(Clone)]
+pub struct InterfacesHook(Arc<dyn Fn(&mut Crossroads) -> Vec<IfaceToken<()>> + Send + Sync>);
+
+impl InterfacesHook {
+    pub fn call(&self, cr: &mut Crossroads) -> Vec<IfaceToken<()>> {
+        (self.0)(cr)
+    }
+}
+
+impl std::fmt::Debug for InterfacesHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("InterfacesHook")
+    }
+}
+
+type ClientCallback = Arc<Mutex<dyn Fn(&str) + Send + 'static>>;
+type ClientCountCallback = Arc<Mutex<dyn Fn(usize) + Send + 'static>>;
+type UnhandledMethodCallback = Arc<Mutex<dyn Fn(&Message) -> Option<Message> + Send + 'static>>;
+type PropertySetCallback =
+    Arc<Mutex<dyn Fn(&str, &str, &dyn RefArg) -> Option<MediaControlEvent> + Send + 'static>>;
+
+#[derive(Clone, Default)]
+struct ClientCallbacks {
+    added: Option<ClientCallback>,
+    removed: Option<ClientCallback>,
+    count_changed: Option<ClientCountCallback>,
+    unhandled_method: Option<UnhandledMethodCallback>,
+    property_set: Option<PropertySetCallback>,
+}
+
+type MatchHandler = (
+    MatchRule<'static>,
+    Arc<Mutex<dyn FnMut(Message, &Connection) -> bool + Send + 'static>>,
+);
+
 struct ServiceThreadHandle {
-    event_channel: mpsc::Sender<InternalEvent>,
-    thread: JoinHandle<Result<(), Error>>,
+    event_channel: waking_channel::Sender<InternalEvent>,
+    state: Arc<Mutex<ServiceState>>,
+    thread: JoinHandle<()>,
+}
+
+struct PolledService {
+    event_channel: waking_channel::Sender<InternalEvent>,
+    service: Service,
 }
 
 #[derive(Clone, PartialEq, Debug)]
 enum InternalEvent {
     ChangeMetadata(OwnedMetadata),
+    MergeMetadata(OwnedMetadata),
+    ChangeDuration(Option<i64>),
+    ChangeNowPlaying(OwnedMetadata, MediaPlayback),
     ChangePlayback(MediaPlayback),
+    SetPosition(MediaPosition),
+    ChangeProgress(MediaPosition),
     ChangeVolume(f64),
+    ChangeRate(f64),
+    ChangeLoopStatus(LoopStatus),
+    ChangeShuffle(bool),
+    ChangeAvailableRates(Vec<f64>),
+    ChangeRating(f64),
     ChangeButtonEnabled(MediaButton, bool),
+    ChangeCanControl(bool),
+    ChangeIdentity(String),
+    ChangeFullscreen(bool),
+    EmptyQueue,
+    Error(String),
+    TrackEnded,
+    FlashMessage(String, Duration),
+    EndFlash,
+    /// Events queued together with [`MediaControls::update`].
+    Batch(Vec<InternalEvent>),
     Kill,
 }
 
 #[derive(Debug)]
 pub struct ServiceState {
+    /// The display name, served as `Identity`.
+    pub identity: String,
+    pub fullscreen: bool,
     pub metadata: OwnedMetadata,
     pub metadata_dict: HashMap<String, Variant<Box<dyn RefArg>>>,
     pub playback_status: MediaPlayback,
+    /// When the playback status was last changed, according to the clock.
+    pub playback_updated_at: Instant,
+    pub clock: Arc<dyn Clock>,
+    /// Whether to clamp the position to the duration, see `PlatformConfig::clamp_position`.
+    pub clamp_position: bool,
     pub volume: f64,
+    /// Set by `MediaControls::set_rating`, and served as `xesam:userRating`.
+    pub rating: Option<f64>,
     pub can_play: bool,
     pub can_pause: bool,
     pub can_go_next: bool,
     pub can_go_previous: bool,
     pub can_seek: bool,
+    pub can_control: bool,
+    pub rate: f64,
+    pub loop_status: LoopStatus,
+    pub shuffle: bool,
+    /// The rates advertised under the `com.souvlaki:availableRates` metadata key.
+    pub available_rates: Vec<f64>,
+    /// A message shown as the title in place of the metadata's, and until when.
+    pub flash: Option<(String, Instant)>,
 }
 
 impl ServiceState {
-    pub fn set_metadata(&mut self, metadata: OwnedMetadata) {
-        self.metadata_dict = create_metadata_dict(&metadata);
+    pub fn new(config: &ServiceConfig) -> Self {
+        Self {
+            identity: config.friendly_name.clone(),
+            fullscreen: false,
+            metadata: Default::default(),
+            metadata_dict: create_metadata_dict(&Default::default()),
+            playback_status: MediaPlayback::Stopped,
+            playback_updated_at: config.clock.now(),
+            clock: config.clock.clone(),
+            clamp_position: config.clamp_position,
+            volume: config.initial_volume,
+            rating: None,
+            can_play: !config.read_only,
+            can_pause: !config.read_only,
+            can_go_next: !config.read_only,
+            can_go_previous: !config.read_only,
+            can_seek: !config.read_only,
+            can_control: !config.read_only,
+            rate: 1.0,
+            loop_status: LoopStatus::None,
+            shuffle: false,
+            available_rates: Vec::new(),
+            flash: None,
+        }
+    }
+
+    /// Whether seeking is enabled and possible in the current media item.
+    pub fn is_seekable(&self) -> bool {
+        self.can_seek && !self.metadata.live
+    }
+
+    /// The capabilities served as the `Can*` properties.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_play: self.can_play,
+            can_pause: self.can_pause,
+            // There is no `CanStop` property.
+            can_stop: true,
+            can_go_next: self.can_go_next,
+            can_go_previous: self.can_go_previous,
+            can_seek: self.is_seekable(),
+            can_control: self.can_control,
+        }
+    }
+
+    /// Set the metadata of the current media item, ending any flashed message.
+    pub fn set_metadata(&mut self, metadata: OwnedMetadata, transform: Option<&MetadataTransform>) {
         self.metadata = metadata;
+        self.flash = None;
+        self.update_metadata_dict(transform);
+    }
+
+    pub fn update_metadata_dict(&mut self, transform: Option<&MetadataTransform>) {
+        let mut metadata = match transform {
+            Some(transform) => transform.apply(self.metadata.clone()),
+            None => self.metadata.clone(),
+        };
+        if let Some((message, _)) = &self.flash {
+            metadata.title = Some(message.clone());
+        }
+        self.metadata_dict = create_metadata_dict(&metadata);
+        if !self.available_rates.is_empty() {
+            self.metadata_dict.insert(
+                AVAILABLE_RATES_METADATA_KEY.to_string(),
+                Variant(Box::new(self.available_rates.clone())),
+            );
+        }
+        if let Some(rating) = self.rating {
+            self.metadata_dict
+                .insert("xesam:userRating".to_string(), Variant(Box::new(rating)));
+        }
+    }
+
+    /// Whether a flashed message is shown and its duration has passed.
+    pub fn is_flash_expired(&self) -> bool {
+        matches!(self.flash, Some((_, until)) if self.clock.now() >= until)
+    }
+
+    pub fn set_playback_status(&mut self, playback: MediaPlayback) {
+        self.playback_status = playback;
+        self.playback_updated_at = self.clock.now();
+    }
+
+    /// When to emit `Seeked` next while playing, given the `seeked_interval` and when
+    /// it was last emitted.
+    pub fn next_seeked_at(
+        &self,
+        interval: Option<Duration>,
+        last_seeked_at: Instant,
+    ) -> Option<Instant> {
+        match (interval, &self.playback_status) {
+            // Clients read the position anew along with the playback status.
+            (Some(interval), MediaPlayback::Playing { .. }) => {
+                Some(last_seeked_at.max(self.playback_updated_at) + interval)
+            }
+            _ => None,
+        }
+    }
+
+    /// Change the playback rate, keeping the position reached so far at the previous one.
+    pub fn set_rate(&mut self, rate: f64) {
+        if let MediaPlayback::Playing { progress: Some(_) } = self.playback_status {
+            let position = MediaPosition(self.get_position());
+            self.set_playback_status(MediaPlayback::Playing {
+                progress: Some(position),
+            });
+        }
+        self.rate = rate;
+    }
+
+    /// Whether a media item is set, i.e. its track id isn't [`TrackId::no_track`].
+    pub fn has_track(&self) -> bool {
+        self.metadata
+            .track_id
+            .as_ref()
+            .map_or(false, |track_id| !track_id.is_no_track())
+    }
+
+    /// The position of the current media item, advanced by the time elapsed since
+    /// it was set, at the playback rate, if playing.
+    pub fn get_position(&self) -> Duration {
+        // Without a track, MPRIS clients expect no position.
+        if !self.has_track() {
+            return Duration::ZERO;
+        }
+        let position = match self.playback_status {
+            MediaPlayback::Playing {
+                progress: Some(progress),
+            } => {
+                let elapsed = self
+                    .clock
+                    .now()
+                    .saturating_duration_since(self.playback_updated_at);
+                progress.0
+                    + Duration::try_from_secs_f64(elapsed.as_secs_f64() * self.rate)
+                        .unwrap_or_default()
+            }
+            MediaPlayback::Paused {
+                progress: Some(progress),
+            } => progress.0,
+            _ => Duration::ZERO,
+        };
+        match self.metadata.duration {
+            Some(duration) if self.clamp_position => {
+                position.min(Duration::from_micros(u64::try_from(duration).unwrap_or(0)))
+            }
+            _ => position,
+        }
+    }
+
+    /// Whether a button is enabled, or `None` for `MediaButton::Stop`, which has no
+    /// property of its own.
+    pub fn is_button_enabled(&self, button: MediaButton) -> Option<bool> {
+        match button {
+            MediaButton::Play => Some(self.can_play),
+            MediaButton::Pause => Some(self.can_pause),
+            MediaButton::Next => Some(self.can_go_next),
+            MediaButton::Previous => Some(self.can_go_previous),
+            MediaButton::Seek => Some(self.can_seek),
+            MediaButton::Stop => None,
+        }
     }
 
     pub fn get_playback_status(&self) -> &'static str {
@@ -72,12 +454,24 @@ pub fn create_metadata_dict(metadata: &OwnedMetadata) -> HashMap<String, Variant
         ref title,
         ref album,
         ref artist,
+        ref artists,
+        ref album_artists,
+        ref track_number,
+        ref disc_number,
+        ref genre,
+        ref content_created,
         ref cover_url,
         ref duration,
+        live: _,
+        ref track_id,
+        ref error,
+        ref chapters,
+        ref extra,
     } = metadata;
 
-    // TODO: this is just a workaround to enable SetPosition.
-    let path = Path::new("/").unwrap();
+    // Track ids are valid object paths.
+    let track_id = track_id.clone().unwrap_or_else(TrackId::no_track);
+    let path = Path::new(track_id.to_string()).unwrap();
 
     // MPRIS
     insert("mpris:trackid", Box::new(path));
@@ -93,23 +487,76 @@ pub fn create_metadata_dict(metadata: &OwnedMetadata) -> HashMap<String, Variant
     if let Some(title) = title {
         insert("xesam:title", Box::new(title.clone()));
     }
-    if let Some(artist) = artist {
+    if !artists.is_empty() {
+        insert("xesam:artist", Box::new(artists.clone()));
+    } else if let Some(artist) = artist {
         insert("xesam:artist", Box::new(vec![artist.clone()]));
     }
     if let Some(album) = album {
         insert("xesam:album", Box::new(album.clone()));
     }
+    if !album_artists.is_empty() {
+        insert("xesam:albumArtist", Box::new(album_artists.clone()));
+    }
+    if let Some(track_number) = track_number {
+        insert("xesam:trackNumber", Box::new(to_i32(*track_number)));
+    }
+    if let Some(disc_number) = disc_number {
+        insert("xesam:discNumber", Box::new(to_i32(*disc_number)));
+    }
+    if !genre.is_empty() {
+        insert("xesam:genre", Box::new(genre.clone()));
+    }
+    if let Some(content_created) = content_created {
+        insert("xesam:contentCreated", Box::new(content_created.clone()));
+    }
+
+    // Custom
+    if let Some(error) = error {
+        insert(ERROR_METADATA_KEY, Box::new(error.clone()));
+    }
+    if !chapters.is_empty() {
+        insert(CHAPTERS_METADATA_KEY, Box::new(chapters.clone()));
+    }
+    for (key, value) in extra {
+        let value: Box<dyn RefArg> = match value {
+            MetadataValue::String(string) => Box::new(string.clone()),
+            MetadataValue::Integer(integer) => Box::new(*integer),
+            MetadataValue::Float(float) => Box::new(*float),
+            MetadataValue::Strings(strings) => Box::new(strings.clone()),
+        };
+        insert(key, value);
+    }
 
     dict
 }
 
+/// An owned version of [`MediaMetadata`], as passed to
+/// [`MediaControls::set_metadata_transform`].
 #[derive(Clone, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OwnedMetadata {
     pub title: Option<String>,
     pub album: Option<String>,
     pub artist: Option<String>,
+    /// Every credited artist. If empty, `artist` is served alone.
+    pub artists: Vec<String>,
+    pub album_artists: Vec<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub genre: Vec<String>,
+    pub content_created: Option<String>,
     pub cover_url: Option<String>,
+    /// The duration in microseconds. Always `None` for live streams.
     pub duration: Option<i64>,
+    pub live: bool,
+    pub track_id: Option<TrackId>,
+    /// Set by `MediaControls::set_error`.
+    pub error: Option<String>,
+    /// The chapters as offsets in microseconds and titles.
+    pub chapters: Vec<(i64, String)>,
+    /// Additional metadata keys, added to the served metadata.
+    pub extra: HashMap<String, MetadataValue>,
 }
 
 impl From<MediaMetadata<'_>> for OwnedMetadata {
@@ -117,10 +564,30 @@ impl From<MediaMetadata<'_>> for OwnedMetadata {
         OwnedMetadata {
             title: other.title.map(|s| s.to_string()),
             artist: other.artist.map(|s| s.to_string()),
+            artists: to_owned_strings(other.artists),
+            album_artists: to_owned_strings(other.album_artists),
+            track_number: other.track_number,
+            disc_number: other.disc_number,
+            genre: to_owned_strings(other.genre),
+            content_created: other.content_created.map(|s| s.to_string()),
             album: other.album.map(|s| s.to_string()),
             cover_url: other.cover_url.map(|s| s.to_string()),
-            // TODO: This should probably not have an unwrap
-            duration: other.duration.map(|d| d.as_micros().try_into().unwrap()),
+            duration: other.duration.filter(|_| !other.live).map(to_micros),
+            live: other.live,
+            track_id: other.track_id.cloned(),
+            error: None,
+            chapters: other
+                .chapters
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(offset, title)| (to_micros(offset), title.to_string()))
+                .collect(),
+            extra: other
+                .extra
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
         }
     }
 }
@@ -131,198 +598,2309 @@ impl MediaControls {
         let PlatformConfig {
             dbus_name,
             display_name,
+            echo_volume,
+            rating_interface,
+            dbus_path_aliases,
+            read_only,
+            seeked_emission,
+            seeked_interval,
+            poll_interval,
+            serve_version_info,
+            dbus_name_flags,
+            dbus_name_pid_suffix,
+            bus,
+            reconnect,
+            round_duration_to_seconds,
+            clamp_position,
+            seeked_on_track_change,
+            initial_volume,
+            minimum_rate,
+            maximum_rate,
+            supported_uri_schemes,
+            supported_mime_types,
+            can_raise,
+            can_quit,
+            can_set_fullscreen,
+            desktop_entry,
+            dummy,
             ..
         } = config;
+        validate_bus_name(dbus_name)?;
 
         Ok(Self {
             thread: None,
+            polled: None,
             dbus_name: dbus_name.to_string(),
-            friendly_name: display_name.to_string(),
+            dbus_name_flags,
+            dbus_name_pid_suffix,
+            bus,
+            reconnect,
+            bus_name: None,
+            config: ServiceConfig {
+                friendly_name: display_name.to_string(),
+                echo_volume: echo_volume && !read_only,
+                rating_interface: rating_interface.map(|s| s.to_string()),
+                path_aliases: dbus_path_aliases.iter().map(|s| s.to_string()).collect(),
+                read_only,
+                volume_scale: VolumeScale::Linear,
+                seeked_emission,
+                seeked_interval,
+                poll_interval: poll_interval.max(Duration::from_millis(1)),
+                serve_version_info,
+                round_duration_to_seconds,
+                clamp_position,
+                seeked_on_track_change,
+                initial_volume: initial_volume.clamp(0.0, 1.0),
+                // The MPRIS spec requires the range to include the normal rate.
+                minimum_rate: minimum_rate.min(1.0),
+                maximum_rate: maximum_rate.max(1.0),
+                supported_uri_schemes: supported_uri_schemes
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect(),
+                supported_mime_types: supported_mime_types.iter().map(|s| s.to_string()).collect(),
+                can_raise: can_raise && !read_only,
+                can_quit: can_quit && !read_only,
+                can_set_fullscreen: can_set_fullscreen && !read_only,
+                desktop_entry: desktop_entry.map(|s| s.to_string()),
+                metadata_transform: None,
+                open_uri_handler: None,
+                interfaces_hook: None,
+                clock: Arc::new(SystemClock),
+                suspended: Arc::new(AtomicBool::new(false)),
+            },
+            last_error: Arc::new(Mutex::new(None)),
+            client_callbacks: Default::default(),
+            match_handlers: Vec::new(),
+            stop_channel: None,
+            setter_state: Default::default(),
+            batch: None,
+            dummy,
         })
     }
 
     /// Attach the media control events to a handler.
+    ///
+    /// Attaching again replaces the previous handler. Returns once the service thread is
+    /// ready to answer clients, or with the error it failed to start with.
     pub fn attach<F>(&mut self, event_handler: F) -> Result<(), Error>
     where
         F: Fn(MediaControlEvent) + Send + 'static,
     {
         self.detach()?;
-
-        let dbus_name = self.dbus_name.clone();
-        let friendly_name = self.friendly_name.clone();
-        let (event_channel, rx) = mpsc::channel();
+        if self.dummy {
+            return Ok(());
+        }
 
         // Check if the connection can be created BEFORE spawning the new thread
-        let conn = Connection::new_session()?;
-        let name = format!("org.mpris.MediaPlayer2.{}", dbus_name);
-        conn.request_name(name, false, true, false)?;
+        let conn = self.connect()?;
+        let reconnect = self.reconnect_config();
+        self.spawn_service(conn, event_handler, reconnect)
+    }
 
-        self.thread = Some(ServiceThreadHandle {
+    /// Attach the media control events to a handler, serving MPRIS on an already opened
+    /// connection instead of a new connection to the [`PlatformConfig::bus`]. (Only available
+    /// on the D-Bus backend)
+    ///
+    /// The connection is moved to the service thread, which from then on is the only one
+    /// processing it: it dispatches the MPRIS method calls, as well as the messages matching
+    /// the rules added with [`MediaControls::add_match`]. Those handlers therefore run on the
+    /// service thread and should return quickly, since no MPRIS call is answered meanwhile.
+    pub fn attach_with_connection<F>(
+        &mut self,
+        conn: Connection,
+        event_handler: F,
+    ) -> Result<(), Error>
+    where
+        F: Fn(MediaControlEvent) + Send + 'static,
+    {
+        self.detach()?;
+        if self.dummy {
+            return Ok(());
+        }
+
+        self.prepare_connection(&conn)?;
+        self.spawn_service(conn, event_handler, None)
+    }
+
+    /// Run the service on the current thread, sending the media control events to a
+    /// handler, until stopped with a [`StopHandle`] from [`MediaControls::stop_handle`].
+    /// The served state is set meanwhile with a handle from [`MediaControls::run_handle`].
+    ///
+    /// **This blocks the calling thread.** No background thread is spawned, so this
+    /// is meant for programs that have nothing else to do, such as media key daemons.
+    /// In dummy mode, this returns right away unless a stop handle was taken beforehand.
+    pub fn run_blocking<F>(&mut self, event_handler: F) -> Result<(), Error>
+    where
+        F: Fn(MediaControlEvent) + Send + 'static,
+    {
+        self.detach()?;
+
+        let stop_channel = self.stop_channel.take();
+        if self.dummy {
+            // Only a stop handle can end the wait, as nothing else is sent in dummy mode.
+            if let Some((_stop_channel, rx)) = stop_channel {
+                rx.recv();
+            }
+            return Ok(());
+        }
+        // Keep a sender alive, so the channel doesn't disconnect without a stop handle.
+        let (_stop_channel, rx) = stop_channel.unwrap_or_else(waking_channel::channel);
+
+        let conn = self.connect()?;
+        self.setter_state.lock().unwrap().disabled_buttons.clear();
+
+        let state = Arc::new(Mutex::new(ServiceState::new(&self.config)));
+        let mut service = Service::new(
+            conn,
+            self.config.clone(),
+            state,
+            self.client_callbacks.clone(),
+            self.match_handlers.clone(),
+            event_handler,
+            rx,
+        )?;
+        service.reconnect = self.reconnect_config();
+        service.run()
+    }
+
+    /// Attach the media control events to a handler without spawning a thread, for
+    /// applications integrating the D-Bus connection into their own `epoll`/`mio` based
+    /// event loop. The service then only runs in [`MediaControls::process_one`], which
+    /// must be called whenever [`MediaControls::watch_fd`] becomes readable, after
+    /// changing anything, such as the metadata, and at the latest after the duration it
+    /// returns. The event handler is called from `process_one`.
+    /// (Only available on the D-Bus backend, Linux only)
+    pub fn attach_polled<F>(&mut self, event_handler: F) -> Result<(), Error>
+    where
+        F: Fn(MediaControlEvent) + Send + 'static,
+    {
+        self.detach()?;
+        if self.dummy {
+            return Ok(());
+        }
+
+        let conn = self.connect()?;
+        self.setter_state.lock().unwrap().disabled_buttons.clear();
+        let (event_channel, rx) = waking_channel::channel();
+        let state = Arc::new(Mutex::new(ServiceState::new(&self.config)));
+        let service = Service::new(
+            conn,
+            self.config.clone(),
+            state,
+            self.client_callbacks.clone(),
+            self.match_handlers.clone(),
+            event_handler,
+            rx,
+        )?;
+        self.polled = Some(PolledService {
             event_channel,
-            thread: thread::spawn(move || run_service(conn, friendly_name, event_handler, rx)),
+            service,
         });
         Ok(())
     }
 
+    /// The file descriptor of the D-Bus connection attached with
+    /// [`MediaControls::attach_polled`], to be watched for readability, or `None` if not
+    /// attached that way. (Only available on the D-Bus backend, Linux only)
+    pub fn watch_fd(&self) -> Option<RawFd> {
+        let polled = self.polled.as_ref()?;
+        Some(polled.service.conn.channel().watch().fd)
+    }
+
+    /// Emit the changes made since the last call and answer the pending method calls,
+    /// without blocking, for controls attached with [`MediaControls::attach_polled`].
+    /// Returns the longest time to wait before calling this again, so that timed changes
+    /// such as the end of [`MediaControls::flash_message`] are emitted in time.
+    /// (Only available on the D-Bus backend, Linux only)
+    pub fn process_one(&mut self) -> Result<Duration, Error> {
+        if self.dummy {
+            return Ok(Duration::from_millis(1000));
+        }
+        let polled = self.polled.as_mut().ok_or(Error::ThreadNotRunning)?;
+        // Only a detach kills the service, which drops it beforehand.
+        let timeout = polled.service.run_once(false)?;
+        Ok(timeout.unwrap_or_default())
+    }
+
+    /// Get a handle to stop the service started by the next call to [`MediaControls::attach`]
+    /// or [`MediaControls::run_blocking`]. Calling [`MediaControls::detach`] from the event
+    /// handler would deadlock, since it waits for the thread the handler runs on, so the
+    /// handler can stop the service with this instead.
+    pub fn stop_handle(&mut self) -> StopHandle {
+        let (stop_channel, _) = self
+            .stop_channel
+            .get_or_insert_with(waking_channel::channel);
+        StopHandle(stop_channel.clone())
+    }
+
+    /// Get a handle to set the metadata, the playback status, the volume and the buttons
+    /// from other threads, without sharing the controls. Changes made through the handle
+    /// aren't part of a [`MediaControls::update`]. The handle stops working once detached,
+    /// so get a new one after attaching again. Returns [`Error::ThreadNotRunning`] if not
+    /// attached, see [`MediaControls::run_handle`] for [`MediaControls::run_blocking`].
+    /// (Only available on MPRIS)
+    pub fn handle(&self) -> Result<MediaControlsHandle, Error> {
+        let event_channel = match self.event_channel() {
+            Some(event_channel) => Some(event_channel.clone()),
+            // Nothing is ever sent in dummy mode.
+            None if self.dummy => None,
+            None => return Err(Error::ThreadNotRunning),
+        };
+        Ok(MediaControlsHandle {
+            event_channel,
+            setter_state: self.setter_state.clone(),
+            round_duration_to_seconds: self.config.round_duration_to_seconds,
+        })
+    }
+
+    /// Get a handle to set the state served by the next call to
+    /// [`MediaControls::run_blocking`] or [`MediaControls::attach`], like
+    /// [`MediaControls::stop_handle`]. Unlike [`MediaControls::handle`], this works before
+    /// the service runs, so it's how the application changes what `run_blocking` serves,
+    /// from the event handler or another thread, while the controls are borrowed. The
+    /// changes made before the service runs are applied once it does. The handle stops
+    /// working once the service ends. (Only available on MPRIS)
+    pub fn run_handle(&mut self) -> MediaControlsHandle {
+        let dummy = self.dummy;
+        let (event_channel, _) = self
+            .stop_channel
+            .get_or_insert_with(waking_channel::channel);
+        MediaControlsHandle {
+            // Nothing is ever sent in dummy mode.
+            event_channel: Some(event_channel.clone()).filter(|_| !dummy),
+            setter_state: self.setter_state.clone(),
+            round_duration_to_seconds: self.config.round_duration_to_seconds,
+        }
+    }
+
     /// Detach the event handler.
+    ///
+    /// The changes sent before detaching, such as a last [`MediaControls::set_metadata`],
+    /// are applied and announced to clients before the bus name is released, including
+    /// those still waiting for [`MediaControls::process_one`] when attached with
+    /// [`MediaControls::attach_polled`]. Nothing is announced afterwards.
     pub fn detach(&mut self) -> Result<(), Error> {
+        if let Some(PolledService {
+            event_channel,
+            mut service,
+        }) = self.polled.take()
+        {
+            event_channel.send(InternalEvent::Kill).ok();
+            // The connection is dropped anyway, releasing the bus name.
+            service.run_once(false).ok();
+        }
+
         if let Some(ServiceThreadHandle {
             event_channel,
             thread,
+            ..
         }) = self.thread.take()
         {
             // We don't care about the result of this event, since we immedieately
             // check if the thread has panicked on the next line.
             event_channel.send(InternalEvent::Kill).ok();
-            // One error in case the thread panics, and the other one in case the
-            // thread has returned an error.
-            thread.join().map_err(|_| Error::ThreadPanicked)??;
+            thread.join().map_err(|_| Error::ThreadPanicked)?;
+
+            // The thread may have returned an error that wasn't retrieved yet.
+            if let Some(err) = self.last_error() {
+                return Err(err);
+            }
         }
         Ok(())
     }
 
+    /// Whether an event handler is attached. The service may have stopped with an error
+    /// since, see [`MediaControls::last_error`].
+    pub fn is_attached(&self) -> bool {
+        self.thread.is_some() || self.polled.is_some()
+    }
+
+    /// The well-known bus name of the service, e.g. `org.mpris.MediaPlayer2.my_player`, with
+    /// the `.instance<pid>` suffix if [`PlatformConfig::dbus_name_pid_suffix`] had to be
+    /// used, or `None` if not attached. (Only available on MPRIS)
+    pub fn well_known_name(&self) -> Option<&str> {
+        self.bus_name.as_deref().filter(|_| self.is_attached())
+    }
+
+    /// Take the error the service thread exited with, if any.
+    ///
+    /// Once taken, the error is cleared, so it's returned only once. This allows
+    /// checking whether the service is still alive without detaching.
+    pub fn last_error(&self) -> Option<Error> {
+        self.last_error.lock().unwrap().take()
+    }
+
     /// Set the current playback status.
     pub fn set_playback(&mut self, playback: MediaPlayback) -> Result<(), Error> {
         self.send_internal_event(InternalEvent::ChangePlayback(playback))
     }
 
+    /// Set the playback status to stopped, and clear the metadata too if `clear_metadata` is
+    /// set. Keep the metadata when the media item stays loaded after stopping, so that
+    /// clients keep showing it, and clear it when there's nothing to resume.
+    ///
+    /// Clearing the metadata leaves only the `mpris:trackid` of [`TrackId::no_track`], sent
+    /// to clients in the same `PropertiesChanged` signal as the status.
+    ///
+    /// The position served to clients is reset to zero, which they read anew along with the
+    /// status, so this is the only call needed to handle [`MediaControlEvent::Stop`].
+    pub fn set_playback_stopped(&mut self, clear_metadata: bool) -> Result<(), Error> {
+        if clear_metadata {
+            return self.set_now_playing(MediaMetadata::default(), MediaPlayback::Stopped);
+        }
+        self.set_playback(MediaPlayback::Stopped)
+    }
+
     /// Set the metadata of the currently playing media item.
+    ///
+    /// A `data:` cover URL too large for [`MediaControls::max_message_size`] is written
+    /// to a temporary file with [`cover_file_url`](crate::cover_file_url) and served
+    /// as a `file://` URL instead.
     pub fn set_metadata(&mut self, metadata: MediaMetadata) -> Result<(), Error> {
-        self.send_internal_event(InternalEvent::ChangeMetadata(metadata.into()))
+        let metadata = self.to_owned_metadata(metadata, false);
+        self.send_internal_event(InternalEvent::ChangeMetadata(metadata))
+    }
+
+    /// Set only the fields of `metadata` that are set, keeping the others, e.g. to add the
+    /// cover art once it's loaded. `live` is only merged when true. Unless set, the track id
+    /// is kept too. Clients are sent the whole merged metadata. (Only available on MPRIS)
+    pub fn merge_metadata(&mut self, metadata: MediaMetadata) -> Result<(), Error> {
+        let metadata = self.to_owned_metadata(metadata, true);
+        self.send_internal_event(InternalEvent::MergeMetadata(metadata))
+    }
+
+    /// Set only the duration of the current media item, keeping the rest of the metadata,
+    /// including the track id, e.g. once the length of a stream becomes known. `None` removes
+    /// it. Clients are sent the whole metadata. (Only available on MPRIS)
+    pub fn set_duration(&mut self, mut duration: Option<Duration>) -> Result<(), Error> {
+        if self.config.round_duration_to_seconds {
+            duration = duration.map(round_to_seconds);
+        }
+        self.send_internal_event(InternalEvent::ChangeDuration(duration.map(to_micros)))
+    }
+
+    /// Set the metadata and the playback status of a new media item at once, e.g. when
+    /// changing tracks. Unlike calling [`MediaControls::set_metadata`] and
+    /// [`MediaControls::set_playback`], this guarantees that clients are sent both in a
+    /// single `PropertiesChanged` signal, followed by `Seeked` if the progress requires
+    /// it according to [`PlatformConfig::seeked_emission`], so they never see the new
+    /// metadata with the previous status.
+    pub fn set_now_playing(
+        &mut self,
+        metadata: MediaMetadata,
+        playback: MediaPlayback,
+    ) -> Result<(), Error> {
+        let metadata = self.to_owned_metadata(metadata, false);
+        self.send_internal_event(InternalEvent::ChangeNowPlaying(metadata, playback))
+    }
+
+    /// Apply the changes made by the setters called in `update` together, so that clients
+    /// are sent them in a single `PropertiesChanged` signal, holding the latest value of
+    /// each property. Changes made before `update` returns an error are still applied.
+    /// (Only available on MPRIS)
+    pub fn update<F>(&mut self, update: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Self) -> Result<(), Error>,
+    {
+        if self.batch.is_some() || self.dummy {
+            // Nested in another update, which sends the events, or nothing to send.
+            return update(self);
+        }
+        self.event_channel().ok_or(Error::ThreadNotRunning)?;
+        self.batch = Some(Vec::new());
+        let result = update(self);
+        let events = self.batch.take().unwrap_or_default();
+        if !events.is_empty() {
+            self.send_internal_event(InternalEvent::Batch(events))?;
+        }
+        result
+    }
+
+    /// The maximum size of the D-Bus messages sent by the service, or `None` if not
+    /// attached. The limit of the bus daemon can't be queried over the bus, so this is
+    /// the default limit of `dbus-daemon`. (Only available on MPRIS)
+    pub fn max_message_size(&self) -> Option<usize> {
+        self.event_channel().map(|_| MAX_MESSAGE_SIZE)
+    }
+
+    /// Set a function fetching the bytes of the cover art at a URL, which Windows and
+    /// macOS call to load cover art from local data. On MPRIS, URLs are passed straight
+    /// to clients, so it's never called.
+    pub fn set_cover_fetcher<F>(&mut self, _fetcher: F)
+    where
+        F: Fn(&str) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+    }
+
+    /// The metadata currently served to clients. Setters are applied by the service
+    /// asynchronously, so this may not reflect a change made right before.
+    /// (Only available on MPRIS)
+    pub fn get_metadata(&self) -> Result<OwnedMetadata, Error> {
+        Ok(self.state()?.metadata.clone())
+    }
+
+    /// The playback status currently served to clients. Like
+    /// [`MediaControls::get_metadata`], this may not reflect a change made right before.
+    /// (Only available on MPRIS)
+    pub fn get_playback(&self) -> Result<MediaPlayback, Error> {
+        Ok(self.state()?.playback_status.clone())
+    }
+
+    /// The volume currently served to clients. Like [`MediaControls::get_metadata`], this
+    /// may not reflect a change made right before. (Only available on MPRIS)
+    pub fn get_volume(&self) -> Result<f64, Error> {
+        Ok(self.state()?.volume)
+    }
+
+    /// Set whether the player is fullscreen, served as the MPRIS `Fullscreen` property.
+    /// (Only available on MPRIS)
+    pub fn set_fullscreen(&mut self, fullscreen: bool) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeFullscreen(fullscreen))
+    }
+
+    /// Change the display name, served as the MPRIS `Identity` property, without
+    /// re-attaching. (Only available on MPRIS)
+    pub fn set_display_name(&mut self, display_name: &str) -> Result<(), Error> {
+        self.config.friendly_name = display_name.to_owned();
+        self.send_internal_event(InternalEvent::ChangeIdentity(display_name.to_owned()))
+    }
+
+    /// Report an error, such as a decoding failure or a lost network connection. MPRIS
+    /// has no error status, so playback is stopped and the message is added to the
+    /// metadata under the custom `com.souvlaki:error` key, for clients that want to show
+    /// it. The message is removed by the next call to [`MediaControls::set_metadata`] or
+    /// [`MediaControls::set_playback`].
+    pub fn set_error(&mut self, message: &str) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::Error(message.to_string()))
+    }
+
+    /// Advertise the discrete playback rates offered by the player, such as
+    /// `[0.5, 1.0, 1.5, 2.0]`, as an array of doubles under the custom
+    /// `com.souvlaki:availableRates` metadata key, for applets that want to show a speed
+    /// menu. The key is omitted when `rates` is empty. MPRIS itself only knows of a
+    /// continuous `Rate`, which is left as it is. (Only available on MPRIS)
+    pub fn set_available_rates(&mut self, rates: &[f64]) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeAvailableRates(rates.to_vec()))
+    }
+
+    /// Show `message` as the title of the current media item for `duration`, after
+    /// which the title is restored. New metadata set in the meantime ends the message
+    /// early. Useful for short notices like "Shuffle on". (Only available on MPRIS)
+    pub fn flash_message(&mut self, message: &str, duration: Duration) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::FlashMessage(message.to_string(), duration))
+    }
+
+    /// Set the position of the current media item, e.g. after seeking from the application,
+    /// keeping the playback status. The MPRIS `Seeked` signal is always emitted, whatever the
+    /// [`PlatformConfig::seeked_emission`], so that clients update their position. Ignored
+    /// while stopped.
+    pub fn set_position(&mut self, position: MediaPosition) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::SetPosition(position))
+    }
+
+    /// Set the progress of the current media item, e.g. from a timer while playing, keeping
+    /// the playback status. As with [`MediaControls::set_playback`], the `Seeked` signal is
+    /// emitted according to the [`PlatformConfig::seeked_emission`]; use
+    /// [`MediaControls::set_position`] after seeking instead. Ignored while stopped.
+    pub fn set_progress(&mut self, progress: Duration) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeProgress(MediaPosition(progress)))
+    }
+
+    /// Report that the current track played until its end. If its duration is known,
+    /// playback is paused at the end of the track and a `Seeked` signal is emitted with
+    /// the duration as position, so that clients show the track as finished instead of
+    /// their position running past it. Otherwise, playback is stopped.
+    ///
+    /// Unlike stopping, this doesn't reset the position, and is meant for the moment
+    /// between two tracks: the next one can be loaded right after with
+    /// [`MediaControls::set_metadata`] and [`MediaControls::set_playback`].
+    pub fn track_ended(&mut self) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::TrackEnded)
+    }
+
+    /// Set the repeat mode served as the `LoopStatus` property. (Only available on MPRIS)
+    pub fn set_loop_status(&mut self, loop_status: LoopStatus) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeLoopStatus(loop_status))
+    }
+
+    /// Set whether playback is shuffled, served as the `Shuffle` property.
+    /// (Only available on MPRIS)
+    pub fn set_shuffle(&mut self, shuffle: bool) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeShuffle(shuffle))
+    }
+
+    /// Set the playback rate served as the `Rate` property, e.g. 1.5 to play 50% faster.
+    /// The position served to clients advances at this rate. (Only available on MPRIS)
+    pub fn set_rate(&mut self, rate: f64) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeRate(rate))
     }
 
-    /// Set the volume level (0.0-1.0) (Only available on MPRIS)
+    /// Set the volume level, where 1.0 is full volume. Values above 1.0 are allowed for
+    /// amplification, as in MPRIS, and negative values are served as 0.0. Returns
+    /// [`Error::InvalidVolume`] for NaN or an infinite volume. (Only available on MPRIS)
     pub fn set_volume(&mut self, volume: f64) -> Result<(), Error> {
-        self.send_internal_event(InternalEvent::ChangeVolume(volume))
+        if !volume.is_finite() {
+            return Err(Error::InvalidVolume(volume));
+        }
+        // Clients are sent the clamped volume, which is also the one read back.
+        self.send_internal_event(InternalEvent::ChangeVolume(volume.max(0.0)))
+    }
+
+    /// Set the volume level in decibels relative to full volume, converted to the
+    /// linear volume served to clients as described in [`VolumeScale::Decibels`].
+    /// (Only available on MPRIS)
+    pub fn set_volume_db(&mut self, volume_db: f64) -> Result<(), Error> {
+        self.set_volume(VolumeScale::Decibels.to_linear(volume_db))
+    }
+
+    /// Set the scale of the volume delivered in [`MediaControlEvent::SetVolume`].
+    /// The `Volume` property served to clients stays linear. Takes effect on the next
+    /// [`MediaControls::attach`]. (Only available on MPRIS)
+    pub fn set_volume_scale(&mut self, scale: VolumeScale) {
+        self.config.volume_scale = scale;
+    }
+
+    /// Set a function transforming the metadata before it is served to clients, e.g. to
+    /// sanitize titles. It is called on the service thread, every time the metadata is
+    /// built, so it should be cheap. Takes effect on the next [`MediaControls::attach`].
+    /// (Only available on MPRIS)
+    pub fn set_metadata_transform<T>(&mut self, transform: T)
+    where
+        T: Fn(OwnedMetadata) -> OwnedMetadata + Send + Sync + 'static,
+    {
+        self.config.metadata_transform = Some(MetadataTransform(Arc::new(transform)));
+    }
+
+    /// Set a function called with the URI a client asks to open, before
+    /// [`MediaControlEvent::OpenUri`] is delivered. The metadata it returns, if any, is
+    /// published right away, so that clients can show the new item while it's loading.
+    ///
+    /// Method calls are handled one at a time, in the order they are received, so the
+    /// event is always delivered before the events of the calls that follow. Takes effect
+    /// on the next [`MediaControls::attach`]. (Only available on MPRIS)
+    pub fn on_open_uri<H>(&mut self, handler: H)
+    where
+        H: Fn(&str) -> Option<OwnedMetadata> + Send + Sync + 'static,
+    {
+        self.config.open_uri_handler = Some(OpenUriHandler(Arc::new(handler)));
+    }
+
+    /// Set the user rating (0.0-1.0) of the current media item, served as `xesam:userRating`
+    /// in the metadata, which widgets show as stars, and on the custom rating interface if
+    /// one was configured with [`PlatformConfig::rating_interface`]. The rating is kept when
+    /// the metadata is set, so set it for every media item, within
+    /// [`MediaControls::update`] to send both together. (Only available on MPRIS)
+    pub fn set_rating(&mut self, rating: f64) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeRating(rating))
     }
 
     /// Enable or disable a specific media control button.
+    ///
+    /// MPRIS has no `CanStop` property, so `MediaButton::Stop` can't be disabled on its own
+    /// and disabling it has no effect. Use [`MediaControls::set_can_control`] to disable every
+    /// control, Stop included.
     pub fn set_button_enabled(&mut self, button: MediaButton, enabled: bool) -> Result<(), Error> {
-        self.send_internal_event(InternalEvent::ChangeButtonEnabled(button, enabled))
+        self.send_internal_event(InternalEvent::ChangeButtonEnabled(button, enabled))?;
+        self.record_button_enabled(button, enabled);
+        Ok(())
     }
 
-    fn send_internal_event(&mut self, event: InternalEvent) -> Result<(), Error> {
-        let thread = &self.thread.as_ref().ok_or(Error::ThreadNotRunning)?;
-        thread
-            .event_channel
-            .send(event)
-            .map_err(|_| Error::ThreadPanicked)
+    /// Set whether clients can control playback at all, served as the `CanControl` property.
+    /// While it's false, clients are expected to disable every control, including Stop which
+    /// can't be disabled with [`MediaControls::set_button_enabled`], and the methods and
+    /// property changes they call anyway are ignored, e.g. for a view-only "now playing"
+    /// display. Always false in [`PlatformConfig::read_only`] mode. (Only available on MPRIS)
+    pub fn set_can_control(&mut self, can_control: bool) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeCanControl(can_control))
     }
-}
 
-fn run_service<F>(
-    conn: Connection,
-    friendly_name: String,
-    event_handler: F,
-    event_channel: mpsc::Receiver<InternalEvent>,
-) -> Result<(), Error>
-where
-    F: Fn(MediaControlEvent) + Send + 'static,
-{
-    let state = Arc::new(Mutex::new(ServiceState {
-        metadata: Default::default(),
-        metadata_dict: create_metadata_dict(&Default::default()),
-        playback_status: MediaPlayback::Stopped,
-        volume: 1.0,
-        can_play: true,
-        can_pause: true,
-        can_go_next: true,
-        can_go_previous: true,
-        can_seek: true,
-    }));
-    let event_handler = Arc::new(Mutex::new(event_handler));
-    let seeked_signal = Arc::new(Mutex::new(None));
-
-    let mut cr =
-        super::interfaces::register_methods(&state, &event_handler, friendly_name, seeked_signal);
-
-    conn.start_receive(
-        dbus::message::MatchRule::new_method_call(),
-        Box::new(move |msg, conn| {
-            cr.handle_message(msg, conn).unwrap();
-            true
-        }),
-    );
+    /// Whether a specific media control button is enabled. While attached, this is read from
+    /// the state served to clients, so that it agrees with [`MediaControls::capabilities`]:
+    /// no button is enabled while [`MediaControls::set_can_control`] is false, and Seek isn't
+    /// while the media item is live. A change made with [`MediaControls::set_button_enabled`]
+    /// is seen once the service has applied it. When not attached, this is as last set with
+    /// [`MediaControls::set_button_enabled`]. There is no `CanStop` property in MPRIS, so
+    /// `MediaButton::Stop` is only disabled along with `CanControl`. No button is enabled in
+    /// [`PlatformConfig::read_only`] mode.
+    pub fn is_button_enabled(&self, button: MediaButton) -> bool {
+        if let Ok(state) = self.state() {
+            return is_served_enabled(state.capabilities(), button);
+        }
+        let setter_state = self.setter_state.lock().unwrap();
+        !self.config.read_only
+            && (button == MediaButton::Stop || !setter_state.disabled_buttons.contains(&button))
+    }
 
-    loop {
-        if let Ok(event) = event_channel.recv_timeout(Duration::from_millis(10)) {
-            if event == InternalEvent::Kill {
-                break;
-            }
+    /// The capabilities served to clients, read together so that they're consistent with
+    /// each other. (Only available on MPRIS)
+    pub fn capabilities(&self) -> Result<Capabilities, Error> {
+        Ok(self.state()?.capabilities())
+    }
 
-            let mut changed_properties = HashMap::new();
+    /// Stop responding to clients temporarily, e.g. during a login prompt, while staying
+    /// registered on the bus and keeping the properties up to date. Until
+    /// [`MediaControls::resume`] is called, the methods called by clients have no effect
+    /// and no event is delivered. The `Can*` properties are left as they are, so that
+    /// clients don't rearrange their controls for a moment: disable buttons with
+    /// [`MediaControls::set_button_enabled`] to grey them out as well.
+    /// Takes effect immediately. (Only available on MPRIS)
+    pub fn suspend(&mut self) {
+        self.config.suspended.store(true, Ordering::Relaxed);
+    }
 
-            match event {
-                InternalEvent::ChangeMetadata(metadata) => {
-                    let mut state = state.lock().unwrap();
-                    state.set_metadata(metadata);
-                    changed_properties.insert(
-                        "Metadata".to_owned(),
-                        Variant(state.metadata_dict.box_clone()),
-                    );
-                }
-                InternalEvent::ChangePlayback(playback) => {
-                    let mut state = state.lock().unwrap();
-                    state.playback_status = playback;
-                    changed_properties.insert(
-                        "PlaybackStatus".to_owned(),
-                        Variant(Box::new(state.get_playback_status().to_string())),
-                    );
-                }
-                InternalEvent::ChangeVolume(volume) => {
-                    let mut state = state.lock().unwrap();
-                    state.volume = volume;
-                    changed_properties.insert("Volume".to_owned(), Variant(Box::new(volume)));
-                }
-                InternalEvent::ChangeButtonEnabled(button, enabled) => {
-                    let mut state = state.lock().unwrap();
-                    match button {
-                        MediaButton::Play => {
-                            state.can_play = enabled;
-                            changed_properties
-                                .insert("CanPlay".to_owned(), Variant(Box::new(enabled)));
-                        }
-                        MediaButton::Pause => {
-                            state.can_pause = enabled;
-                            changed_properties
-                                .insert("CanPause".to_owned(), Variant(Box::new(enabled)));
-                        }
-                        MediaButton::Next => {
-                            state.can_go_next = enabled;
-                            changed_properties
-                                .insert("CanGoNext".to_owned(), Variant(Box::new(enabled)));
-                        }
-                        MediaButton::Previous => {
-                            state.can_go_previous = enabled;
-                            changed_properties
-                                .insert("CanGoPrevious".to_owned(), Variant(Box::new(enabled)));
-                        }
-                        MediaButton::Seek => {
-                            state.can_seek = enabled;
-                            changed_properties
-                                .insert("CanSeek".to_owned(), Variant(Box::new(enabled)));
-                        }
-                        MediaButton::Stop => {
-                            // MPRIS doesn't have a separate CanStop property
-                        }
-                    }
-                }
-                _ => (),
-            }
+    /// Respond to clients again after [`MediaControls::suspend`]. (Only available on MPRIS)
+    pub fn resume(&mut self) {
+        self.config.suspended.store(false, Ordering::Relaxed);
+    }
 
-            let properties_changed = PropertiesPropertiesChanged {
-                interface_name: "org.mpris.MediaPlayer2.Player".to_owned(),
-                changed_properties,
-                invalidated_properties: Vec::new(),
-            };
+    /// Set the clock used to advance the reported position while playing. Takes effect
+    /// on the next [`MediaControls::attach`]. (Only available on MPRIS)
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.config.clock = clock;
+    }
 
-            conn.send(
-                properties_changed.to_emit_message(&Path::new("/org/mpris/MediaPlayer2").unwrap()),
-            )
-            .ok();
+    /// Set the state for when there's nothing to play: playback is stopped and the play,
+    /// pause, next, previous and seek controls are disabled. Enable them again with
+    /// [`MediaControls::set_button_enabled`] once something is loaded.
+    pub fn set_empty_queue(&mut self) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::EmptyQueue)?;
+        for button in EMPTY_QUEUE_DISABLED_BUTTONS {
+            self.record_button_enabled(button, false);
         }
-        conn.process(Duration::from_millis(1000))?;
+        Ok(())
+    }
+
+    /// Set a callback invoked with the unique bus name of a client the first time it
+    /// talks to the player, e.g. by reading its properties. Takes effect on the next
+    /// [`MediaControls::attach`]. (Only available on the D-Bus backend)
+    pub fn on_client_added<F>(&mut self, callback: F)
+    where
+        F: Fn(&str) + Send + 'static,
+    {
+        self.client_callbacks.added = Some(Arc::new(Mutex::new(callback)));
+    }
+
+    /// Set a callback invoked with the unique bus name of a client that was previously
+    /// reported by [`MediaControls::on_client_added`] once it disconnects from the bus.
+    /// Takes effect on the next [`MediaControls::attach`]. (Only available on the D-Bus backend)
+    pub fn on_client_removed<F>(&mut self, callback: F)
+    where
+        F: Fn(&str) + Send + 'static,
+    {
+        self.client_callbacks.removed = Some(Arc::new(Mutex::new(callback)));
+    }
+
+    /// Set a callback invoked with the number of clients whenever it changes, e.g. to skip
+    /// fetching cover art while nobody is watching. Clients are counted as with
+    /// [`MediaControls::on_client_added`] and [`MediaControls::on_client_removed`], so a
+    /// client only listening to signals without ever talking to the player isn't counted.
+    /// Takes effect on the next [`MediaControls::attach`]. (Only available on the D-Bus backend)
+    pub fn on_client_count_changed<F>(&mut self, callback: F)
+    where
+        F: Fn(usize) + Send + 'static,
+    {
+        self.client_callbacks.count_changed = Some(Arc::new(Mutex::new(callback)));
+    }
+
+    /// Set a callback invoked with the method calls the service doesn't implement, such as
+    /// vendor extensions, instead of only answering them with an `UnknownMethod` error. The
+    /// interface, member and arguments can be read from the message, and returning a reply,
+    /// e.g. made with `Message::method_return`, sends it in place of the error.
+    /// Takes effect on the next [`MediaControls::attach`]. (Only available on the D-Bus backend)
+    pub fn on_unhandled_method<F>(&mut self, callback: F)
+    where
+        F: Fn(&Message) -> Option<Message> + Send + 'static,
+    {
+        self.client_callbacks.unhandled_method = Some(Arc::new(Mutex::new(callback)));
+    }
+
+    /// Set a callback invoked with the interface, name and value of the properties clients
+    /// try to set but the service rejects: unknown properties, and values of the wrong type
+    /// or out of range, such as a non-standard toggle. The event it returns is delivered as
+    /// if set by the client, e.g. [`MediaControlEvent::ToggleShuffle`], answering the call
+    /// with success instead of the error.
+    ///
+    /// Valid sets of the standard properties always take precedence and are never passed to
+    /// the callback: `Shuffle` and `LoopStatus` set to a value are delivered as
+    /// [`MediaControlEvent::SetShuffle`] and [`MediaControlEvent::SetLoopStatus`].
+    /// Takes effect on the next [`MediaControls::attach`]. (Only available on the D-Bus backend)
+    pub fn on_property_set<F>(&mut self, callback: F)
+    where
+        F: Fn(&str, &str, &dyn RefArg) -> Option<MediaControlEvent> + Send + 'static,
+    {
+        self.client_callbacks.property_set = Some(Arc::new(Mutex::new(callback)));
     }
 
-    Ok(())
+    /// Dispatch the messages matching a rule to a handler on the connection the service
+    /// runs on, e.g. to listen to signals of other services. Returning `false` from the
+    /// handler removes it. The rule is added to the bus when attaching, so this takes
+    /// effect on the next [`MediaControls::attach`]. (Only available on the D-Bus backend)
+    ///
+    /// See [`MediaControls::attach_with_connection`] for the threading model.
+    pub fn add_match<H>(&mut self, rule: MatchRule<'static>, handler: H)
+    where
+        H: FnMut(Message, &Connection) -> bool + Send + 'static,
+    {
+        self.match_handlers
+            .push((rule, Arc::new(Mutex::new(handler))));
+    }
+
+    /// Register more interfaces on the MPRIS object at [`MPRIS_OBJECT_PATH`](crate::MPRIS_OBJECT_PATH),
+    /// served next to the MPRIS ones. `register` is called with the `Crossroads` instance of
+    /// the service on every attach, and returns the interfaces to add. Takes effect on the
+    /// next [`MediaControls::attach`]. (Only available on the D-Bus backend)
+    pub fn register_interfaces<R>(&mut self, register: R)
+    where
+        R: Fn(&mut Crossroads) -> Vec<IfaceToken<()>> + Send + Sync + 'static,
+    {
+        self.config.interfaces_hook = Some(InterfacesHook(Arc::new(register)));
+    }
+
+    fn connect(&mut self) -> Result<Connection, Error> {
+        let conn = match self.bus {
+            BusType::Session => Connection::new_session(),
+            BusType::System => Connection::new_system(),
+        }
+        .map_err(to_connect_error)?;
+        self.prepare_connection(&conn)?;
+        Ok(conn)
+    }
+
+    /// How to connect again to the bus the controls were just connected to, if configured to.
+    fn reconnect_config(&self) -> Option<Reconnect> {
+        let bus_name = self.bus_name.clone().filter(|_| self.reconnect)?;
+        Some(Reconnect {
+            bus: self.bus,
+            bus_name,
+            flags: self.dbus_name_flags,
+        })
+    }
+
+    fn prepare_connection(&mut self, conn: &Connection) -> Result<(), Error> {
+        if let Some(rating_interface) = &self.config.rating_interface {
+            dbus::strings::Interface::new(rating_interface.as_str())
+                .map_err(|msg| dbus::Error::new_failed(&msg))?;
+        }
+        for alias in &self.config.path_aliases {
+            Path::new(alias.as_str()).map_err(|msg| dbus::Error::new_failed(&msg))?;
+        }
+
+        let name = format!("{}{}", BUS_NAME_PREFIX, self.dbus_name);
+        let BusNameFlags {
+            allow_replacement,
+            replace_existing,
+            do_not_queue,
+        } = self.dbus_name_flags;
+        let request_name =
+            |name: &str| conn.request_name(name, allow_replacement, replace_existing, do_not_queue);
+        let name = match request_name(&name)? {
+            RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => name,
+            RequestNameReply::InQueue if !self.dbus_name_pid_suffix => {
+                // Waiting for the name, as the flags allow.
+                name
+            }
+            reply => {
+                if !self.dbus_name_pid_suffix {
+                    return Err(Error::NameAlreadyOwned);
+                }
+                if matches!(reply, RequestNameReply::InQueue) {
+                    conn.release_name(name.as_str())?;
+                }
+                let name = format!("{}.instance{}", name, std::process::id());
+                match request_name(&name)? {
+                    RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => name,
+                    _ => return Err(Error::NameAlreadyOwned),
+                }
+            }
+        };
+        self.bus_name = Some(name);
+        Ok(())
+    }
+
+    /// Run the service on a new thread, waiting until it started.
+    fn spawn_service<F>(
+        &mut self,
+        conn: Connection,
+        event_handler: F,
+        reconnect: Option<Reconnect>,
+    ) -> Result<(), Error>
+    where
+        F: Fn(MediaControlEvent) + Send + 'static,
+    {
+        let config = self.config.clone();
+        let last_error = self.last_error.clone();
+        let client_callbacks = self.client_callbacks.clone();
+        let match_handlers = self.match_handlers.clone();
+        let (event_channel, rx) = self
+            .stop_channel
+            .take()
+            .unwrap_or_else(waking_channel::channel);
+        let (ready_sender, ready) = mpsc::channel();
+        let state = Arc::new(Mutex::new(ServiceState::new(&self.config)));
+        self.setter_state.lock().unwrap().disabled_buttons.clear();
+
+        let thread = thread::Builder::new()
+            .name(service_thread_name(&self.dbus_name))
+            .spawn({
+                let state = state.clone();
+                move || {
+                    let result = Service::new(
+                        conn,
+                        config,
+                        state,
+                        client_callbacks,
+                        match_handlers,
+                        event_handler,
+                        rx,
+                    )
+                    .and_then(|mut service| {
+                        service.reconnect = reconnect;
+                        ready_sender.send(()).ok();
+                        service.run()
+                    });
+                    if let Err(err) = result {
+                        *last_error.lock().unwrap() = Some(err);
+                    }
+                }
+            })
+            .map_err(Error::ThreadSpawnFailed)?;
+        self.thread = Some(ServiceThreadHandle {
+            event_channel,
+            state,
+            thread,
+        });
+
+        // The thread drops the sender without a signal if the service fails to start,
+        // returning the error detaching reports.
+        if ready.recv().is_err() {
+            self.detach()?;
+            return Err(Error::ThreadNotRunning);
+        }
+        Ok(())
+    }
+
+    fn record_button_enabled(&mut self, button: MediaButton, enabled: bool) {
+        let mut setter_state = self.setter_state.lock().unwrap();
+        setter_state.record_button_enabled(button, enabled);
+    }
+
+    /// The state of the service, whether it runs on a thread or is polled.
+    fn state(&self) -> Result<MutexGuard<'_, ServiceState>, Error> {
+        let thread = self.thread.as_ref().map(|thread| &thread.state);
+        let state = thread
+            .or_else(|| self.polled.as_ref().map(|polled| &polled.service.state))
+            .ok_or(Error::ThreadNotRunning)?;
+        Ok(state.lock().unwrap())
+    }
+
+    /// The channel to the service, whether it runs on a thread or is polled.
+    fn event_channel(&self) -> Option<&waking_channel::Sender<InternalEvent>> {
+        let thread = self.thread.as_ref().map(|thread| &thread.event_channel);
+        thread.or_else(|| self.polled.as_ref().map(|polled| &polled.event_channel))
+    }
+
+    /// Prepare metadata to be sent to the service, as configured.
+    /// If `merged`, the metadata is merged into the current one, which keeps its track id and
+    /// cover unless they are set.
+    fn to_owned_metadata(&self, metadata: MediaMetadata, merged: bool) -> OwnedMetadata {
+        self.setter_state.lock().unwrap().to_owned_metadata(
+            metadata,
+            merged,
+            self.config.round_duration_to_seconds,
+            self.max_message_size(),
+        )
+    }
+
+    fn send_internal_event(&mut self, event: InternalEvent) -> Result<(), Error> {
+        if self.dummy {
+            return Ok(());
+        }
+        if let Some(batch) = &mut self.batch {
+            batch.push(event);
+            return Ok(());
+        }
+        let event_channel = self.event_channel().ok_or(Error::ThreadNotRunning)?;
+        // The channel is disconnected once the service thread has stopped, as for the handles.
+        event_channel
+            .send(event)
+            .map_err(|_| Error::ThreadNotRunning)
+    }
+}
+
+/// Convert an error connecting to the bus, telling a missing bus apart.
+fn to_connect_error(err: dbus::Error) -> Error {
+    let is_no_bus = err.name().map_or(false, |name| {
+        matches!(
+            name,
+            "org.freedesktop.DBus.Error.NoServer"
+                | "org.freedesktop.DBus.Error.FileNotFound"
+                | "org.freedesktop.DBus.Error.BadAddress"
+                | "org.freedesktop.DBus.Error.NotSupported"
+        ) || name.starts_with("org.freedesktop.DBus.Error.Spawn.")
+    });
+    if is_no_bus {
+        Error::NoBus(err.message().unwrap_or_default().to_owned())
+    } else {
+        err.into()
+    }
+}
+
+/// The delay before the first attempt to connect again, doubled after each failure.
+const RECONNECT_DELAY: Duration = Duration::from_millis(250);
+/// The longest delay between two attempts to connect again.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// How the service connects to the bus again, see [`PlatformConfig::reconnect`].
+#[derive(Clone, Debug)]
+struct Reconnect {
+    bus: BusType,
+    /// The well-known name owned before the connection was lost.
+    bus_name: String,
+    flags: BusNameFlags,
+}
+
+impl Reconnect {
+    fn connect(&self) -> Result<Connection, Error> {
+        let conn = match self.bus {
+            BusType::Session => Connection::new_session(),
+            BusType::System => Connection::new_system(),
+        }
+        .map_err(to_connect_error)?;
+        let BusNameFlags {
+            allow_replacement,
+            replace_existing,
+            do_not_queue,
+        } = self.flags;
+        let reply = conn.request_name(
+            self.bus_name.as_str(),
+            allow_replacement,
+            replace_existing,
+            do_not_queue,
+        )?;
+        match reply {
+            RequestNameReply::Exists => Err(Error::NameAlreadyOwned),
+            _ => Ok(conn),
+        }
+    }
+}
+
+/// The MPRIS service, run either on its own thread or from the application's event loop.
+struct Service {
+    conn: Connection,
+    config: ServiceConfig,
+    state: Arc<Mutex<ServiceState>>,
+    paths: Vec<Path<'static>>,
+    event_channel: waking_channel::Receiver<InternalEvent>,
+    last_seeked_at: Instant,
+    /// Serves the MPRIS interfaces, on every connection the service was served on.
+    cr: Arc<Mutex<Crossroads>>,
+    /// The application's handler, for the events even a read-only or suspended player
+    /// delivers.
+    event_handler: Arc<Mutex<dyn Fn(MediaControlEvent) + Send>>,
+    /// The handler of the events from clients, dropping them while they can't control the
+    /// player.
+    client_event_handler: Arc<Mutex<dyn Fn(MediaControlEvent) + Send>>,
+    client_callbacks: ClientCallbacks,
+    match_handlers: Vec<MatchHandler>,
+    /// The unique names of the clients that called a method, if they're tracked.
+    clients: Arc<Mutex<HashSet<String>>>,
+    /// How to connect again if the connection is lost, if configured to.
+    reconnect: Option<Reconnect>,
+}
+
+impl Service {
+    fn new<F>(
+        conn: Connection,
+        config: ServiceConfig,
+        state: Arc<Mutex<ServiceState>>,
+        client_callbacks: ClientCallbacks,
+        match_handlers: Vec<MatchHandler>,
+        event_handler: F,
+        mut event_channel: waking_channel::Receiver<InternalEvent>,
+    ) -> Result<Self, Error>
+    where
+        F: Fn(MediaControlEvent) + Send + 'static,
+    {
+        event_channel.make_wakeable().map_err(Error::WakerFailed)?;
+
+        // Clients can't control a read-only or suspended player, but the application is
+        // still told about the connection.
+        let read_only = config.read_only;
+        let suspended = config.suspended.clone();
+        let unfiltered_handler = Arc::new(Mutex::new(event_handler));
+        let event_handler = {
+            let event_handler = unfiltered_handler.clone();
+            move |event: MediaControlEvent| {
+                if !read_only && !suspended.load(Ordering::Relaxed) {
+                    deliver_event(&*event_handler.lock().unwrap(), event);
+                }
+            }
+        };
+
+        let event_handler = Arc::new(Mutex::new(event_handler));
+        let cr = super::interfaces::register_methods(&state, &event_handler, &config);
+
+        // The aliases were validated when connecting.
+        let paths: Vec<Path<'static>> = std::iter::once(MPRIS_OBJECT_PATH.to_string())
+            .chain(config.path_aliases.iter().cloned())
+            .map(|path| Path::new(path).unwrap())
+            .collect();
+
+        let last_seeked_at = config.clock.now();
+        let service = Self {
+            conn,
+            config,
+            state,
+            paths,
+            event_channel,
+            last_seeked_at,
+            cr: Arc::new(Mutex::new(cr)),
+            event_handler: unfiltered_handler,
+            client_event_handler: event_handler,
+            client_callbacks,
+            match_handlers,
+            clients: Default::default(),
+            reconnect: None,
+        };
+        service.serve()?;
+        Ok(service)
+    }
+
+    /// Receive the method calls and the matched messages of the connection.
+    fn serve(&self) -> Result<(), Error> {
+        let Self {
+            conn,
+            state,
+            cr,
+            client_event_handler,
+            client_callbacks,
+            match_handlers,
+            clients,
+            ..
+        } = self;
+
+        // Messages go to the first matching receiver, so these must be added before the
+        // receiver taking every method call.
+        for (rule, handler) in match_handlers.iter().cloned() {
+            conn.add_match_no_cb(&rule.match_str())?;
+            conn.start_receive(
+                rule,
+                Box::new(move |msg, conn| (handler.lock().unwrap())(msg, conn)),
+            );
+        }
+
+        let track_clients = client_callbacks.added.is_some()
+            || client_callbacks.removed.is_some()
+            || client_callbacks.count_changed.is_some();
+
+        conn.start_receive(
+            dbus::message::MatchRule::new_method_call(),
+            Box::new({
+                let cr = cr.clone();
+                let state = state.clone();
+                let client_event_handler = client_event_handler.clone();
+                let clients = clients.clone();
+                let on_added = client_callbacks.added.clone();
+                let on_count_changed = client_callbacks.count_changed.clone();
+                let on_unhandled_method = client_callbacks.unhandled_method.clone();
+                let on_property_set = client_callbacks.property_set.clone();
+
+                move |msg, conn| {
+                    if let Some(sender) = msg.sender().filter(|_| track_clients) {
+                        let is_new = clients.lock().unwrap().insert(sender.to_string());
+                        if is_new {
+                            if let Some(on_added) = &on_added {
+                                (on_added.lock().unwrap())(&sender);
+                            }
+                            if let Some(on_count_changed) = &on_count_changed {
+                                let count = clients.lock().unwrap().len();
+                                (on_count_changed.lock().unwrap())(count);
+                            }
+                        }
+                    }
+
+                    log::trace!(
+                        "method call {}.{} from {}",
+                        msg.interface().as_deref().unwrap_or(""),
+                        msg.member().as_deref().unwrap_or(""),
+                        msg.sender().as_deref().unwrap_or("")
+                    );
+                    // Kept for the callbacks, as dispatching consumes the call.
+                    let call = (on_unhandled_method.is_some() || on_property_set.is_some())
+                        .then(|| msg.duplicate().ok())
+                        .flatten();
+                    let replies = super::interfaces::dispatch(&mut cr.lock().unwrap(), msg);
+                    match replies {
+                        Ok(replies) => {
+                            for mut msg in replies {
+                                if let (Some(on_property_set), Some(call)) =
+                                    (&on_property_set, &call)
+                                {
+                                    let can_control = state.lock().unwrap().can_control;
+                                    let event = rejected_property_set(call, &mut msg)
+                                        .filter(|_| can_control)
+                                        .and_then(|(interface, name, value)| {
+                                            (on_property_set.lock().unwrap())(
+                                                interface, name, &*value.0,
+                                            )
+                                        });
+                                    if let Some(event) = event {
+                                        (client_event_handler.lock().unwrap())(event);
+                                        msg = call.method_return();
+                                    }
+                                }
+                                if let (Some(on_unhandled_method), Some(call)) =
+                                    (&on_unhandled_method, &call)
+                                {
+                                    if is_unknown_method_error(&mut msg) {
+                                        let reply = (on_unhandled_method.lock().unwrap())(call);
+                                        msg = reply.unwrap_or(msg);
+                                    }
+                                }
+                                if conn.send(msg).is_err() {
+                                    log::warn!("failed to send a method reply");
+                                }
+                            }
+                        }
+                        Err(()) => log::warn!("failed to handle a method call"),
+                    }
+                    true
+                }
+            }),
+        );
+
+        if track_clients {
+            // A client is gone once its unique name loses its owner.
+            let rule =
+                dbus::message::MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged")
+                    .with_sender("org.freedesktop.DBus");
+            let clients = clients.clone();
+            let on_removed = client_callbacks.removed.clone();
+            let on_count_changed = client_callbacks.count_changed.clone();
+
+            conn.add_match(
+                rule,
+                move |(name, _old_owner, new_owner): (String, String, String), _, _| {
+                    if new_owner.is_empty() && clients.lock().unwrap().remove(&name) {
+                        if let Some(on_removed) = &on_removed {
+                            (on_removed.lock().unwrap())(&name);
+                        }
+                        if let Some(on_count_changed) = &on_count_changed {
+                            let count = clients.lock().unwrap().len();
+                            (on_count_changed.lock().unwrap())(count);
+                        }
+                    }
+                    true
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Run until killed, connecting again whenever the connection is lost if configured to.
+    fn run(&mut self) -> Result<(), Error> {
+        loop {
+            let err = match self.run_once(true) {
+                Ok(Some(_)) => continue,
+                Ok(None) => return Ok(()),
+                Err(err) => err,
+            };
+            let reconnect = match self.reconnect.clone() {
+                Some(reconnect) => reconnect,
+                None => return Err(err),
+            };
+
+            log::warn!("lost the connection to the bus: {}", err);
+            self.forget_clients();
+            self.deliver_event(MediaControlEvent::ConnectionLost);
+            if !self.reconnect(&reconnect)? {
+                return Ok(());
+            }
+            log::debug!("connected to the bus again as {}", reconnect.bus_name);
+            self.deliver_event(MediaControlEvent::Reconnected);
+        }
+    }
+
+    /// Connect again, waiting longer after each failure. Returns `false` if killed
+    /// meanwhile.
+    fn reconnect(&mut self, reconnect: &Reconnect) -> Result<bool, Error> {
+        let mut delay = RECONNECT_DELAY;
+        loop {
+            if !self.wait_disconnected(delay) {
+                return Ok(false);
+            }
+            match reconnect.connect() {
+                Ok(conn) => {
+                    self.conn = conn;
+                    self.serve()?;
+                    return Ok(true);
+                }
+                Err(err) => log::debug!("failed to connect to the bus again: {}", err),
+            }
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
+
+    /// Wait for `delay` while disconnected, still handling the events sent meanwhile so
+    /// that the state is up to date once connected again. Returns `false` if killed.
+    fn wait_disconnected(&mut self, delay: Duration) -> bool {
+        let until = Instant::now() + delay;
+        loop {
+            while let Some(event) = self.event_channel.try_recv() {
+                if event == InternalEvent::Kill {
+                    return false;
+                }
+                // Clients read the whole state again once connected, so nothing is emitted.
+                handle_internal_event(
+                    &mut self.state.lock().unwrap(),
+                    &self.config,
+                    event,
+                    &mut ChangedProperties::new(),
+                    &mut None,
+                );
+            }
+            let now = Instant::now();
+            if now >= until {
+                return true;
+            }
+            // There's no connection to watch, only the channel.
+            self.event_channel.wait(-1, 0, until - now);
+        }
+    }
+
+    /// Deliver an event about the service itself, whether or not clients can control it.
+    fn deliver_event(&self, event: MediaControlEvent) {
+        deliver_event(&*self.event_handler.lock().unwrap(), event);
+    }
+
+    /// Report every tracked client as removed, as they're gone with the connection.
+    fn forget_clients(&self) {
+        let clients: Vec<_> = self.clients.lock().unwrap().drain().collect();
+        if clients.is_empty() {
+            return;
+        }
+        if let Some(on_removed) = &self.client_callbacks.removed {
+            for name in &clients {
+                (on_removed.lock().unwrap())(name);
+            }
+        }
+        if let Some(on_count_changed) = &self.client_callbacks.count_changed {
+            (on_count_changed.lock().unwrap())(0);
+        }
+    }
+
+    /// Handle the pending events, emitting the resulting signals, and process the
+    /// incoming messages. If `blocking`, this waits for a message, a new event or a timed
+    /// change in between. Returns `None` once killed, or else the longest time to wait
+    /// before running again.
+    fn run_once(&mut self, blocking: bool) -> Result<Option<Duration>, Error> {
+        let Self {
+            conn,
+            config,
+            state,
+            paths,
+            event_channel,
+            last_seeked_at,
+        } = self;
+
+        let event = event_channel.try_recv();
+        let mut seeked = None;
+        let mut killed = false;
+
+        let event = event.or_else(|| {
+            let is_flash_expired = state.lock().unwrap().is_flash_expired();
+            is_flash_expired.then_some(InternalEvent::EndFlash)
+        });
+
+        if let Some(event) = event {
+            // Handle every pending event at once, so that changes made together
+            // are emitted together.
+            let mut changed_properties = ChangedProperties::new();
+
+            for event in std::iter::once(event).chain(event_channel.try_iter()) {
+                if event == InternalEvent::Kill {
+                    killed = true;
+                    break;
+                }
+                log::trace!("handling {:?}", event);
+                handle_internal_event(
+                    &mut state.lock().unwrap(),
+                    config,
+                    event,
+                    &mut changed_properties,
+                    &mut seeked,
+                );
+            }
+
+            for signal in properties_changed_signals(changed_properties, paths) {
+                if conn.send(signal).is_err() {
+                    log::warn!("failed to send PropertiesChanged");
+                }
+            }
+        }
+
+        let state = state.lock().unwrap();
+        let is_seeked_due = state
+            .next_seeked_at(config.seeked_interval, *last_seeked_at)
+            .map_or(false, |at| config.clock.now() >= at);
+        if is_seeked_due {
+            seeked.get_or_insert_with(|| state.get_position());
+        }
+
+        if let Some(position) = seeked {
+            *last_seeked_at = config.clock.now();
+            let position = to_micros(position);
+            log::debug!("emitting Seeked at {}us", position);
+            for path in paths.iter() {
+                let seeked_signal =
+                    Message::signal(path, &PLAYER_INTERFACE.into(), &"Seeked".into())
+                        .append1(position);
+                if conn.send(seeked_signal).is_err() {
+                    log::warn!("failed to send Seeked on {}", path);
+                }
+            }
+        }
+
+        if killed {
+            // Send the last signals before the connection is dropped.
+            conn.channel().flush();
+            return Ok(None);
+        }
+
+        // Wake up in time for the next periodic `Seeked` and to end a flashed message.
+        let now = config.clock.now();
+        let mut timeout = config.poll_interval;
+        if let Some(at) = state.next_seeked_at(config.seeked_interval, *last_seeked_at) {
+            timeout = timeout.min(at.saturating_duration_since(now));
+        }
+        if let Some((_, until)) = &state.flash {
+            timeout = timeout.min(until.saturating_duration_since(now));
+        }
+        // The state is also locked while answering method calls.
+        drop(state);
+
+        if blocking {
+            let channel = conn.channel();
+            let mut events = libc::POLLIN;
+            if channel.has_messages_to_send() {
+                events |= libc::POLLOUT;
+            }
+            event_channel.wait(channel.watch().fd, events, timeout);
+        }
+        // Sends the pending messages too.
+        while conn.process(Duration::ZERO)? {}
+        Ok(Some(timeout))
+    }
+}
+
+/// Whether `msg` is the error Crossroads answers a call to a method it doesn't serve with.
+fn is_unknown_method_error(msg: &mut Message) -> bool {
+    let name = msg
+        .as_result()
+        .err()
+        .and_then(|err| err.name().map(str::to_owned));
+    matches!(
+        name.as_deref(),
+        Some("org.freedesktop.DBus.Error.UnknownMethod")
+            | Some("org.freedesktop.DBus.Error.UnknownInterface")
+            | Some("org.freedesktop.DBus.Error.UnknownObject")
+    )
+}
+
+/// The `PropertiesChanged` signals announcing the changed properties on every path.
+/// Each interface's properties must be announced in a separate signal.
+fn properties_changed_signals(
+    changed_properties: ChangedProperties,
+    paths: &[Path<'static>],
+) -> Vec<Message> {
+    let mut signals = Vec::new();
+    for (interface_name, changed_properties) in changed_properties {
+        log::debug!(
+            "emitting PropertiesChanged on {} for {:?}",
+            interface_name,
+            changed_properties.keys().collect::<Vec<_>>()
+        );
+        let properties_changed = PropertiesPropertiesChanged {
+            interface_name,
+            changed_properties,
+            invalidated_properties: Vec::new(),
+        };
+        signals.extend(
+            paths
+                .iter()
+                .map(|path| properties_changed.to_emit_message(path)),
+        );
+    }
+    signals
+}
+
+/// The interface, name and value of a `Properties.Set` call, if `reply` is the error the
+/// service rejected it with, for the callback of [`MediaControls::on_property_set`].
+fn rejected_property_set<'a>(
+    call: &'a Message,
+    reply: &mut Message,
+) -> Option<(&'a str, &'a str, Variant<Box<dyn RefArg>>)> {
+    let is_set = call.interface().as_deref() == Some("org.freedesktop.DBus.Properties")
+        && call.member().as_deref() == Some("Set");
+    if !is_set {
+        return None;
+    }
+    let name = reply
+        .as_result()
+        .err()
+        .and_then(|err| err.name().map(str::to_owned));
+    let is_rejected = matches!(
+        name.as_deref(),
+        Some("org.freedesktop.DBus.Error.UnknownProperty")
+            | Some("org.freedesktop.DBus.Error.UnknownInterface")
+            | Some("org.freedesktop.DBus.Error.InvalidArgs")
+    );
+    is_rejected.then(|| call.read3().ok()).flatten()
+}
+
+/// Changed property values, grouped by the interface they belong to.
+type ChangedProperties = HashMap<String, HashMap<String, Variant<Box<dyn RefArg>>>>;
+
+const APP_INTERFACE: &str = "org.mpris.MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+const INFO_INTERFACE: &str = "com.souvlaki.Info";
+
+const EMPTY_QUEUE_DISABLED_BUTTONS: [MediaButton; 5] = [
+    MediaButton::Play,
+    MediaButton::Pause,
+    MediaButton::Next,
+    MediaButton::Previous,
+    MediaButton::Seek,
+];
+
+fn handle_internal_event(
+    state: &mut ServiceState,
+    config: &ServiceConfig,
+    event: InternalEvent,
+    changed_properties: &mut ChangedProperties,
+    seeked: &mut Option<Duration>,
+) {
+    let mut changed = |interface: &str, name: &str, value: Box<dyn RefArg>| {
+        changed_properties
+            .entry(interface.to_owned())
+            .or_default()
+            .insert(name.to_owned(), Variant(value));
+    };
+
+    // Unchanged values aren't announced again, so that setters called on every frame
+    // don't flood the bus.
+    match event {
+        InternalEvent::ChangeMetadata(metadata)
+            if metadata == state.metadata && state.flash.is_none() => {}
+        InternalEvent::ChangeMetadata(metadata) => {
+            let was_seekable = state.is_seekable();
+            let is_new_track = metadata.track_id != state.metadata.track_id;
+            state.set_metadata(metadata, config.metadata_transform.as_ref());
+            changed(
+                PLAYER_INTERFACE,
+                "Metadata",
+                state.metadata_dict.box_clone(),
+            );
+            if state.is_seekable() != was_seekable {
+                changed(PLAYER_INTERFACE, "CanSeek", Box::new(state.is_seekable()));
+            }
+            if is_new_track && config.seeked_on_track_change {
+                if let Some(playback) =
+                    with_position(&state.playback_status, MediaPosition(Duration::ZERO))
+                {
+                    state.set_playback_status(playback);
+                    *seeked = Some(Duration::ZERO);
+                }
+            }
+        }
+        InternalEvent::ChangePlayback(playback) => {
+            if state.metadata.error.is_some() {
+                let metadata = OwnedMetadata {
+                    error: None,
+                    ..state.metadata.clone()
+                };
+                state.set_metadata(metadata, config.metadata_transform.as_ref());
+                changed(
+                    PLAYER_INTERFACE,
+                    "Metadata",
+                    state.metadata_dict.box_clone(),
+                );
+            }
+            if let MediaPlayback::Playing {
+                progress: Some(progress),
+            }
+            | MediaPlayback::Paused {
+                progress: Some(progress),
+            } = playback
+            {
+                if is_seek(config.seeked_emission, state.get_position(), progress.0) {
+                    *seeked = Some(progress.0);
+                }
+            }
+            let previous_status = state.get_playback_status();
+            // The progress is still updated, as it moves on even with the same status.
+            state.set_playback_status(playback);
+            if state.get_playback_status() != previous_status {
+                changed(
+                    PLAYER_INTERFACE,
+                    "PlaybackStatus",
+                    Box::new(state.get_playback_status().to_string()),
+                );
+            }
+        }
+        InternalEvent::SetPosition(position) => {
+            // Only the position changes, which clients learn from `Seeked`.
+            if let Some(playback) = with_position(&state.playback_status, position) {
+                state.set_playback_status(playback);
+                *seeked = Some(position.0);
+            }
+        }
+        InternalEvent::ChangeProgress(position) => {
+            if let Some(playback) = with_position(&state.playback_status, position) {
+                handle_internal_event(
+                    state,
+                    config,
+                    InternalEvent::ChangePlayback(playback),
+                    changed_properties,
+                    seeked,
+                );
+            }
+        }
+        InternalEvent::ChangeVolume(volume) if volume == state.volume => {}
+        InternalEvent::ChangeVolume(volume) => {
+            state.volume = volume;
+            changed(PLAYER_INTERFACE, "Volume", Box::new(volume));
+        }
+        InternalEvent::ChangeRate(rate) if rate == state.rate => {}
+        InternalEvent::ChangeRate(rate) => {
+            state.set_rate(rate);
+            changed(PLAYER_INTERFACE, "Rate", Box::new(rate));
+        }
+        InternalEvent::ChangeLoopStatus(loop_status) if loop_status == state.loop_status => {}
+        InternalEvent::ChangeLoopStatus(loop_status) => {
+            state.loop_status = loop_status;
+            changed(
+                PLAYER_INTERFACE,
+                "LoopStatus",
+                Box::new(loop_status_name(loop_status).to_string()),
+            );
+        }
+        InternalEvent::ChangeShuffle(shuffle) if shuffle == state.shuffle => {}
+        InternalEvent::ChangeShuffle(shuffle) => {
+            state.shuffle = shuffle;
+            changed(PLAYER_INTERFACE, "Shuffle", Box::new(shuffle));
+        }
+        InternalEvent::ChangeAvailableRates(rates) if rates == state.available_rates => {}
+        InternalEvent::ChangeAvailableRates(rates) => {
+            state.available_rates = rates;
+            state.update_metadata_dict(config.metadata_transform.as_ref());
+            changed(
+                PLAYER_INTERFACE,
+                "Metadata",
+                state.metadata_dict.box_clone(),
+            );
+        }
+        InternalEvent::ChangeRating(rating) if state.rating == Some(rating) => {}
+        InternalEvent::ChangeRating(rating) => {
+            state.rating = Some(rating);
+            state.update_metadata_dict(config.metadata_transform.as_ref());
+            changed(
+                PLAYER_INTERFACE,
+                "Metadata",
+                state.metadata_dict.box_clone(),
+            );
+            if let Some(rating_interface) = &config.rating_interface {
+                changed(rating_interface, "Rating", Box::new(rating));
+            }
+        }
+        InternalEvent::ChangeButtonEnabled(..) if config.read_only => {
+            // Every button stays disabled.
+        }
+        InternalEvent::ChangeButtonEnabled(button, enabled)
+            if state.is_button_enabled(button) == Some(enabled) => {}
+        InternalEvent::ChangeButtonEnabled(button, enabled) => match button {
+            MediaButton::Play => {
+                state.can_play = enabled;
+                changed(PLAYER_INTERFACE, "CanPlay", Box::new(enabled));
+            }
+            MediaButton::Pause => {
+                state.can_pause = enabled;
+                changed(PLAYER_INTERFACE, "CanPause", Box::new(enabled));
+            }
+            MediaButton::Next => {
+                state.can_go_next = enabled;
+                changed(PLAYER_INTERFACE, "CanGoNext", Box::new(enabled));
+            }
+            MediaButton::Previous => {
+                state.can_go_previous = enabled;
+                changed(PLAYER_INTERFACE, "CanGoPrevious", Box::new(enabled));
+            }
+            MediaButton::Seek => {
+                state.can_seek = enabled;
+                changed(PLAYER_INTERFACE, "CanSeek", Box::new(state.is_seekable()));
+            }
+            MediaButton::Stop => {
+                // MPRIS doesn't have a separate CanStop property, only CanControl.
+            }
+        },
+        InternalEvent::ChangeCanControl(..) if config.read_only => {
+            // Control stays disabled.
+        }
+        InternalEvent::ChangeCanControl(can_control) if can_control == state.can_control => {}
+        InternalEvent::ChangeCanControl(can_control) => {
+            state.can_control = can_control;
+            changed(PLAYER_INTERFACE, "CanControl", Box::new(can_control));
+        }
+        InternalEvent::MergeMetadata(metadata) => {
+            let mut merged = state.metadata.clone();
+            merged.merge(metadata);
+            handle_internal_event(
+                state,
+                config,
+                InternalEvent::ChangeMetadata(merged),
+                changed_properties,
+                seeked,
+            );
+        }
+        InternalEvent::ChangeDuration(duration) => {
+            let metadata = OwnedMetadata {
+                // Live media items have no duration.
+                duration: duration.filter(|_| !state.metadata.live),
+                ..state.metadata.clone()
+            };
+            handle_internal_event(
+                state,
+                config,
+                InternalEvent::ChangeMetadata(metadata),
+                changed_properties,
+                seeked,
+            );
+        }
+        InternalEvent::ChangeIdentity(identity) if identity == state.identity => {}
+        InternalEvent::ChangeIdentity(identity) => {
+            state.identity = identity;
+            changed(APP_INTERFACE, "Identity", Box::new(state.identity.clone()));
+            if config.serve_version_info {
+                changed(
+                    INFO_INTERFACE,
+                    "DisplayName",
+                    Box::new(state.identity.clone()),
+                );
+            }
+        }
+        InternalEvent::ChangeFullscreen(fullscreen) if fullscreen == state.fullscreen => {}
+        InternalEvent::ChangeFullscreen(fullscreen) => {
+            state.fullscreen = fullscreen;
+            changed(APP_INTERFACE, "Fullscreen", Box::new(fullscreen));
+        }
+        InternalEvent::ChangeNowPlaying(metadata, playback) => {
+            handle_internal_event(
+                state,
+                config,
+                InternalEvent::ChangeMetadata(metadata),
+                changed_properties,
+                seeked,
+            );
+            // A new media item is expected to start at the beginning, not where the
+            // previous one was.
+            if let Some(playback) =
+                with_position(&state.playback_status, MediaPosition(Duration::ZERO))
+            {
+                state.set_playback_status(playback);
+            }
+            handle_internal_event(
+                state,
+                config,
+                InternalEvent::ChangePlayback(playback),
+                changed_properties,
+                seeked,
+            );
+            // Announce where the new media item actually starts.
+            if let Some(position) = seeked {
+                *position = state.get_position();
+            }
+        }
+        InternalEvent::EmptyQueue => {
+            // Handled as a whole, so that the changes are always emitted together.
+            let events = std::iter::once(InternalEvent::ChangePlayback(MediaPlayback::Stopped))
+                .chain(
+                    EMPTY_QUEUE_DISABLED_BUTTONS
+                        .iter()
+                        .map(|&button| InternalEvent::ChangeButtonEnabled(button, false)),
+                );
+            for event in events {
+                handle_internal_event(state, config, event, changed_properties, seeked);
+            }
+        }
+        InternalEvent::Error(message) => {
+            // Not through `ChangePlayback`, which clears the error.
+            let metadata = OwnedMetadata {
+                error: Some(message),
+                ..state.metadata.clone()
+            };
+            state.set_metadata(metadata, config.metadata_transform.as_ref());
+            changed(
+                PLAYER_INTERFACE,
+                "Metadata",
+                state.metadata_dict.box_clone(),
+            );
+            let previous_status = state.get_playback_status();
+            state.set_playback_status(MediaPlayback::Stopped);
+            if state.get_playback_status() != previous_status {
+                changed(
+                    PLAYER_INTERFACE,
+                    "PlaybackStatus",
+                    Box::new(state.get_playback_status().to_string()),
+                );
+            }
+        }
+        InternalEvent::TrackEnded => {
+            // Rest at the end of the track, so that the position doesn't run past it.
+            let playback = match state.metadata.duration.map(u64::try_from) {
+                Some(Ok(duration)) => {
+                    let position = Duration::from_micros(duration);
+                    *seeked = Some(position);
+                    MediaPlayback::Paused {
+                        progress: Some(MediaPosition(position)),
+                    }
+                }
+                _ => MediaPlayback::Stopped,
+            };
+            handle_internal_event(
+                state,
+                config,
+                InternalEvent::ChangePlayback(playback),
+                changed_properties,
+                seeked,
+            );
+        }
+        InternalEvent::FlashMessage(message, duration) => {
+            state.flash = Some((message, config.clock.now() + duration));
+            state.update_metadata_dict(config.metadata_transform.as_ref());
+            changed(
+                PLAYER_INTERFACE,
+                "Metadata",
+                state.metadata_dict.box_clone(),
+            );
+        }
+        InternalEvent::EndFlash => {
+            // The flash may have already been ended by new metadata.
+            if state.flash.take().is_some() {
+                state.update_metadata_dict(config.metadata_transform.as_ref());
+                changed(
+                    PLAYER_INTERFACE,
+                    "Metadata",
+                    state.metadata_dict.box_clone(),
+                );
+            }
+        }
+        InternalEvent::Batch(events) => {
+            for event in events {
+                handle_internal_event(state, config, event, changed_properties, seeked);
+            }
+        }
+        InternalEvent::Kill => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ManualClock, SeekDirection};
+
+    /// The settings of controls created with `config`, without connecting to a bus.
+    fn service_config(config: PlatformConfig) -> ServiceConfig {
+        MediaControls::new(config).unwrap().config
+    }
+
+    /// Handle `event` like the service, returning the changed properties.
+    fn handle(
+        state: &mut ServiceState,
+        config: &ServiceConfig,
+        event: InternalEvent,
+    ) -> ChangedProperties {
+        let mut changed_properties = ChangedProperties::new();
+        handle_internal_event(state, config, event, &mut changed_properties, &mut None);
+        changed_properties
+    }
+
+    #[test]
+    fn error_is_served_in_metadata() {
+        let config = service_config(PlatformConfig::default());
+        let mut state = ServiceState::new(&config);
+        handle(
+            &mut state,
+            &config,
+            InternalEvent::ChangePlayback(MediaPlayback::playing(Duration::ZERO)),
+        );
+
+        let changed = handle(&mut state, &config, InternalEvent::Error("boom".into()));
+
+        assert_eq!(state.metadata.error.as_deref(), Some("boom"));
+        let error = &state.metadata_dict[ERROR_METADATA_KEY];
+        assert_eq!(error.0.as_str(), Some("boom"));
+        assert_eq!(state.get_playback_status(), "Stopped");
+        let changed = &changed[PLAYER_INTERFACE];
+        assert!(changed.contains_key("Metadata"));
+        assert!(changed.contains_key("PlaybackStatus"));
+    }
+
+    #[test]
+    fn identical_metadata_is_not_announced_again() {
+        let config = service_config(PlatformConfig::default());
+        let mut state = ServiceState::new(&config);
+        let mut setter_state = SetterState::default();
+        let metadata = MediaMetadata {
+            title: Some("Song"),
+            ..Default::default()
+        };
+
+        for is_announced in [true, false] {
+            let metadata = setter_state.to_owned_metadata(metadata.clone(), false, false, None);
+            let changed = handle(&mut state, &config, InternalEvent::ChangeMetadata(metadata));
+            assert_eq!(changed.contains_key(PLAYER_INTERFACE), is_announced);
+        }
+    }
+
+    #[test]
+    fn refreshed_metadata_is_not_a_track_change() {
+        let config = service_config(PlatformConfig {
+            seeked_on_track_change: true,
+            ..Default::default()
+        });
+        let mut state = ServiceState::new(&config);
+        let mut setter_state = SetterState::default();
+        let song = MediaMetadata {
+            title: Some("Song"),
+            ..Default::default()
+        };
+        let with_cover = MediaMetadata {
+            cover_url: Some("file:///cover.png"),
+            ..song.clone()
+        };
+
+        let mut seeked = None;
+        for (metadata, is_track_change) in [(song, true), (with_cover, false)] {
+            let playback = MediaPlayback::paused(Duration::from_secs(42));
+            handle(&mut state, &config, InternalEvent::ChangePlayback(playback));
+            let metadata = setter_state.to_owned_metadata(metadata, false, false, None);
+            handle_internal_event(
+                &mut state,
+                &config,
+                InternalEvent::ChangeMetadata(metadata),
+                &mut ChangedProperties::new(),
+                &mut seeked,
+            );
+            assert_eq!(seeked.take().is_some(), is_track_change);
+            let position = if is_track_change { 0 } else { 42 };
+            assert_eq!(state.get_position(), Duration::from_secs(position));
+        }
+    }
+
+    #[test]
+    fn rejected_property_set_is_passed_on() {
+        let mut call = Message::new_method_call(
+            "org.mpris.MediaPlayer2.souvlaki_player",
+            MPRIS_OBJECT_PATH,
+            "org.freedesktop.DBus.Properties",
+            "Set",
+        )
+        .unwrap()
+        .append3(PLAYER_INTERFACE, "Shuffle", Variant("toggle"));
+        call.set_serial(1);
+
+        let mut reply = call.error(
+            &"org.freedesktop.DBus.Error.InvalidArgs".into(),
+            &std::ffi::CString::new("invalid").unwrap(),
+        );
+        let (interface, name, value) = rejected_property_set(&call, &mut reply).unwrap();
+        assert_eq!((interface, name), (PLAYER_INTERFACE, "Shuffle"));
+        assert_eq!(value.0.as_str(), Some("toggle"));
+
+        let mut reply = call.method_return();
+        assert!(rejected_property_set(&call, &mut reply).is_none());
+    }
+
+    /// The strings under `key` of a metadata dict, checking they're served as `as`.
+    fn dict_strings(
+        dict: &HashMap<String, Variant<Box<dyn RefArg>>>,
+        key: &str,
+    ) -> Option<Vec<String>> {
+        let value = &dict.get(key)?.0;
+        assert_eq!(&*value.signature(), "as", "signature of {}", key);
+        let strings = value.as_iter().unwrap();
+        Some(strings.map(|s| s.as_str().unwrap().to_owned()).collect())
+    }
+
+    #[test]
+    fn metadata_lists_are_string_arrays() {
+        fn to_strings(strings: &[&str]) -> Vec<String> {
+            strings.iter().map(|s| s.to_string()).collect()
+        }
+
+        for values in [&["One"][..], &["One", "Two", "Three"][..]] {
+            let metadata = OwnedMetadata {
+                artists: to_strings(values),
+                album_artists: to_strings(values),
+                genre: to_strings(values),
+                ..Default::default()
+            };
+            let dict = create_metadata_dict(&metadata);
+            for key in ["xesam:artist", "xesam:albumArtist", "xesam:genre"] {
+                assert_eq!(dict_strings(&dict, key), Some(to_strings(values)));
+            }
+        }
+    }
+
+    #[test]
+    fn single_artist_is_a_string_array() {
+        let metadata = OwnedMetadata {
+            artist: Some("One".to_string()),
+            ..Default::default()
+        };
+        let dict = create_metadata_dict(&metadata);
+        assert_eq!(
+            dict_strings(&dict, "xesam:artist"),
+            Some(vec!["One".to_string()])
+        );
+    }
+
+    #[test]
+    fn empty_metadata_lists_are_omitted() {
+        let dict = create_metadata_dict(&OwnedMetadata::default());
+        for key in ["xesam:artist", "xesam:albumArtist", "xesam:genre"] {
+            assert!(!dict.contains_key(key), "{} is served", key);
+        }
+    }
+
+    #[test]
+    fn batched_changes_are_announced_per_interface() {
+        let config = service_config(PlatformConfig::default());
+        let mut state = ServiceState::new(&config);
+        let batch = InternalEvent::Batch(vec![
+            InternalEvent::ChangeFullscreen(true),
+            InternalEvent::ChangeVolume(0.5),
+        ]);
+        let changed = handle(&mut state, &config, batch);
+
+        let paths = [Path::new(MPRIS_OBJECT_PATH).unwrap()];
+        let mut signals: Vec<_> = properties_changed_signals(changed, &paths)
+            .iter()
+            .map(|signal| {
+                assert_eq!(signal.member().as_deref(), Some("PropertiesChanged"));
+                let (interface, changed): (String, HashMap<String, Variant<Box<dyn RefArg>>>) =
+                    signal.read2().unwrap();
+                (interface, changed.into_keys().collect::<Vec<_>>())
+            })
+            .collect();
+        signals.sort();
+        assert_eq!(
+            signals,
+            [
+                (APP_INTERFACE.to_string(), vec!["Fullscreen".to_string()]),
+                (PLAYER_INTERFACE.to_string(), vec!["Volume".to_string()]),
+            ]
+        );
+    }
+
+    /// A state following a manual clock, with a media item set so that it has a position.
+    fn state_with_manual_clock(config: PlatformConfig) -> (ServiceState, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new());
+        let mut config = service_config(config);
+        config.clock = clock.clone();
+        let mut state = ServiceState::new(&config);
+        let metadata = OwnedMetadata {
+            track_id: Some(TrackId::new("/org/souvlaki/test/track").unwrap()),
+            duration: Some(to_micros(Duration::from_secs(60))),
+            ..Default::default()
+        };
+        state.set_metadata(metadata, None);
+        (state, clock)
+    }
+
+    #[test]
+    fn position_advances_with_the_clock_while_playing() {
+        let (mut state, clock) = state_with_manual_clock(PlatformConfig::default());
+        state.set_playback_status(MediaPlayback::playing(Duration::from_secs(10)));
+        assert_eq!(state.get_position(), Duration::from_secs(10));
+
+        clock.advance(Duration::from_millis(2500));
+        assert_eq!(state.get_position(), Duration::from_millis(12500));
+
+        state.set_playback_status(MediaPlayback::paused(state.get_position()));
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(state.get_position(), Duration::from_millis(12500));
+    }
+
+    #[test]
+    fn progress_keeps_the_playback_status() {
+        let config = service_config(PlatformConfig::default());
+        let (mut state, _clock) = state_with_manual_clock(PlatformConfig::default());
+        let progress = InternalEvent::ChangeProgress(MediaPosition(Duration::from_secs(20)));
+
+        state.set_playback_status(MediaPlayback::paused(Duration::from_secs(10)));
+        assert!(handle(&mut state, &config, progress).is_empty());
+        assert_eq!(
+            state.playback_status,
+            MediaPlayback::paused(Duration::from_secs(20))
+        );
+
+        state.set_playback_status(MediaPlayback::Stopped);
+        let progress = InternalEvent::ChangeProgress(MediaPosition(Duration::from_secs(20)));
+        assert!(handle(&mut state, &config, progress).is_empty());
+        assert_eq!(state.playback_status, MediaPlayback::Stopped);
+    }
+
+    #[test]
+    fn position_advances_at_the_playback_rate() {
+        let (mut state, clock) = state_with_manual_clock(PlatformConfig::default());
+        state.set_playback_status(MediaPlayback::playing(Duration::from_secs(10)));
+        clock.advance(Duration::from_secs(1));
+
+        // The position reached at the previous rate is kept.
+        state.set_rate(2.0);
+        assert_eq!(state.get_position(), Duration::from_secs(11));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(state.get_position(), Duration::from_secs(13));
+    }
+
+    #[test]
+    fn position_is_clamped_to_the_duration() {
+        let (mut state, clock) = state_with_manual_clock(PlatformConfig {
+            clamp_position: true,
+            ..Default::default()
+        });
+        state.set_playback_status(MediaPlayback::playing(Duration::from_secs(59)));
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(state.get_position(), Duration::from_secs(60));
+    }
+
+    /// The MPRIS methods served without a bus, with the events they delivered.
+    struct Dispatcher {
+        cr: Crossroads,
+        state: Arc<Mutex<ServiceState>>,
+        events: Arc<Mutex<Vec<MediaControlEvent>>>,
+    }
+
+    impl Dispatcher {
+        /// Serve the methods for `state`, with a media item playing at 10s.
+        fn new(config: &ServiceConfig, mut state: ServiceState) -> Self {
+            let metadata = OwnedMetadata {
+                track_id: Some(TrackId::new(TEST_TRACK_ID).unwrap()),
+                duration: Some(to_micros(Duration::from_secs(60))),
+                ..state.metadata.clone()
+            };
+            state.set_metadata(metadata, None);
+            state.set_playback_status(MediaPlayback::playing(Duration::from_secs(10)));
+
+            let state = Arc::new(Mutex::new(state));
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let event_handler = Arc::new(Mutex::new({
+                let (state, events) = (state.clone(), events.clone());
+                move |event: MediaControlEvent| {
+                    // Handlers may read the state, e.g. with `MediaControls::get_metadata`,
+                    // which would deadlock if it was still locked.
+                    let is_locked = state.try_lock().is_err();
+                    assert!(!is_locked, "{:?} delivered with the state locked", event);
+                    events.lock().unwrap().push(event);
+                }
+            }));
+            let cr = super::super::interfaces::register_methods(&state, &event_handler, config);
+            Self { cr, state, events }
+        }
+
+        /// Call a method of the Player interface, returning the messages sent in response.
+        fn call<A: dbus::arg::AppendAll>(&mut self, member: &str, args: A) -> Vec<Message> {
+            self.call_on(PLAYER_INTERFACE, member, args)
+        }
+
+        /// Set a property through the standard Properties interface, returning the messages
+        /// sent in response.
+        fn set_property(&mut self, interface: &str, name: &str, value: f64) -> Vec<Message> {
+            let args = (interface, name, Variant(value));
+            self.call_on("org.freedesktop.DBus.Properties", "Set", args)
+        }
+
+        fn call_on<A: dbus::arg::AppendAll>(
+            &mut self,
+            interface: &str,
+            member: &str,
+            args: A,
+        ) -> Vec<Message> {
+            let mut call = Message::new_method_call(
+                "org.mpris.MediaPlayer2.souvlaki_player",
+                MPRIS_OBJECT_PATH,
+                interface,
+                member,
+            )
+            .unwrap();
+            args.append(&mut dbus::arg::IterAppend::new(&mut call));
+            // Replies need the serial of the call.
+            call.set_serial(1);
+            super::super::interfaces::dispatch(&mut self.cr, call).unwrap()
+        }
+
+        fn take_events(&self) -> Vec<MediaControlEvent> {
+            std::mem::take(&mut *self.events.lock().unwrap())
+        }
+    }
+
+    const TEST_TRACK_ID: &str = "/org/souvlaki/test/track";
+
+    fn is_seeked_signal(msg: &Message) -> bool {
+        msg.msg_type() == dbus::MessageType::Signal && msg.member().as_deref() == Some("Seeked")
+    }
+
+    #[test]
+    fn play_pause_is_delivered_as_toggle() {
+        let config = service_config(PlatformConfig::default());
+        let mut dispatcher = Dispatcher::new(&config, ServiceState::new(&config));
+
+        let replies = dispatcher.call("PlayPause", ());
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].msg_type(), dbus::MessageType::MethodReturn);
+        assert_eq!(dispatcher.take_events(), [MediaControlEvent::Toggle]);
+    }
+
+    #[test]
+    fn stop_is_delivered_unless_control_is_disabled() {
+        let config = service_config(PlatformConfig::default());
+        let mut dispatcher = Dispatcher::new(&config, ServiceState::new(&config));
+
+        dispatcher.call("Stop", ());
+        assert_eq!(dispatcher.take_events(), [MediaControlEvent::Stop]);
+
+        dispatcher.state.lock().unwrap().can_control = false;
+        dispatcher.call("Stop", ());
+        assert!(dispatcher.take_events().is_empty());
+    }
+
+    #[test]
+    fn set_position_for_a_stale_track_is_ignored() {
+        let config = service_config(PlatformConfig::default());
+        let mut dispatcher = Dispatcher::new(&config, ServiceState::new(&config));
+        let position = to_micros(Duration::from_secs(5));
+
+        let stale_track_id = Path::new("/org/souvlaki/test/previous").unwrap();
+        dispatcher.call("SetPosition", (stale_track_id, position));
+        assert!(dispatcher.take_events().is_empty());
+
+        let track_id = Path::new(TEST_TRACK_ID).unwrap();
+        dispatcher.call("SetPosition", (track_id, position));
+        assert_eq!(
+            dispatcher.take_events(),
+            [MediaControlEvent::SetPosition(MediaPosition(
+                Duration::from_secs(5)
+            ))]
+        );
+    }
+
+    #[test]
+    fn set_position_is_delivered_with_the_state_unlocked() {
+        let config = service_config(PlatformConfig::default());
+        let mut dispatcher = Dispatcher::new(&config, ServiceState::new(&config));
+
+        // The handler of the dispatcher fails if it can't lock the state.
+        let track_id = Path::new(TEST_TRACK_ID).unwrap();
+        dispatcher.call("SetPosition", (track_id, to_micros(Duration::ZERO)));
+        assert_eq!(
+            dispatcher.take_events(),
+            [MediaControlEvent::SetPosition(MediaPosition(
+                Duration::ZERO
+            ))]
+        );
+    }
+
+    #[test]
+    fn seek_is_ignored_while_seeking_is_disabled() {
+        let config = service_config(PlatformConfig::default());
+        let mut dispatcher = Dispatcher::new(&config, ServiceState::new(&config));
+        dispatcher.state.lock().unwrap().can_seek = false;
+
+        let replies = dispatcher.call("Seek", (to_micros(Duration::from_secs(5)),));
+        assert!(!replies.iter().any(is_seeked_signal));
+        assert!(dispatcher.take_events().is_empty());
+    }
+
+    #[test]
+    fn seek_is_ignored_for_live_media() {
+        let config = service_config(PlatformConfig::default());
+        let mut dispatcher = Dispatcher::new(&config, ServiceState::new(&config));
+        {
+            let mut state = dispatcher.state.lock().unwrap();
+            let metadata = OwnedMetadata {
+                live: true,
+                ..state.metadata.clone()
+            };
+            state.set_metadata(metadata, None);
+        }
+
+        let replies = dispatcher.call("Seek", (to_micros(Duration::from_secs(5)),));
+        assert!(!replies.iter().any(is_seeked_signal));
+        assert!(dispatcher.take_events().is_empty());
+    }
+
+    #[test]
+    fn seek_is_ignored_once_the_button_is_disabled() {
+        let config = service_config(PlatformConfig::default());
+        let mut dispatcher = Dispatcher::new(&config, ServiceState::new(&config));
+        let offset = to_micros(Duration::from_secs(5));
+
+        // `Seeked` is only emitted once the application sets the new position.
+        let replies = dispatcher.call("Seek", (offset,));
+        assert!(!replies.iter().any(is_seeked_signal));
+        assert_eq!(
+            dispatcher.take_events(),
+            [MediaControlEvent::SeekBy(
+                SeekDirection::Forward,
+                Duration::from_secs(5)
+            )]
+        );
+
+        handle(
+            &mut dispatcher.state.lock().unwrap(),
+            &config,
+            InternalEvent::ChangeButtonEnabled(MediaButton::Seek, false),
+        );
+        let replies = dispatcher.call("Seek", (offset,));
+        assert!(!replies.iter().any(is_seeked_signal));
+        assert!(dispatcher.take_events().is_empty());
+    }
+
+    const TEST_RATING_INTERFACE: &str = "com.souvlaki.test.Rating";
+
+    #[test]
+    fn rating_is_ignored_without_control() {
+        let config = service_config(PlatformConfig {
+            rating_interface: Some(TEST_RATING_INTERFACE),
+            ..Default::default()
+        });
+        let mut dispatcher = Dispatcher::new(&config, ServiceState::new(&config));
+
+        dispatcher.set_property(TEST_RATING_INTERFACE, "Rating", 0.5);
+        assert_eq!(
+            dispatcher.take_events(),
+            [MediaControlEvent::SetRating(0.5)]
+        );
+
+        dispatcher.state.lock().unwrap().can_control = false;
+        dispatcher.set_property(TEST_RATING_INTERFACE, "Rating", 0.5);
+        assert!(dispatcher.take_events().is_empty());
+    }
+
+    #[test]
+    fn rating_out_of_range_is_rejected() {
+        let config = service_config(PlatformConfig {
+            rating_interface: Some(TEST_RATING_INTERFACE),
+            ..Default::default()
+        });
+        let mut dispatcher = Dispatcher::new(&config, ServiceState::new(&config));
+
+        for rating in [-0.1, 1.1, f64::NAN, f64::INFINITY] {
+            let replies = dispatcher.set_property(TEST_RATING_INTERFACE, "Rating", rating);
+            assert_eq!(replies[0].msg_type(), dbus::MessageType::Error);
+        }
+        assert!(dispatcher.take_events().is_empty());
+    }
 }
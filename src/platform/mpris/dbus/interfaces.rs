@@ -1,72 +1,140 @@
 use std::{
-    convert::{TryFrom, TryInto},
-    sync::{Arc, Mutex},
+    cell::RefCell,
+    collections::HashMap,
+    convert::TryFrom,
+    sync::{atomic::Ordering, Arc, Mutex},
     time::Duration,
 };
 
-use dbus::Path;
+use dbus::arg::{RefArg, Variant};
+use dbus::channel::Sender;
+use dbus::ffidisp::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+use dbus::message::SignalArgs;
+use dbus::{Message, MethodErr, Path};
 use dbus_crossroads::{Crossroads, IfaceBuilder};
 
-use crate::{MediaControlEvent, MediaPlayback, MediaPosition, SeekDirection};
+use crate::{MediaButton, MediaControlEvent, MediaPosition, SeekDirection};
 
-use super::controls::{create_metadata_dict, ServiceState};
-
-// TODO: This type is super messed up, but it's the only way to get seeking working properly
-// on graphical media controls using dbus-crossroads.
-pub type SeekedSignal =
-    Arc<Mutex<Option<Box<dyn Fn(&Path<'_>, &(String,)) -> dbus::Message + Send + Sync>>>>;
+use super::super::{loop_status_name, parse_loop_status, to_micros, MPRIS_OBJECT_PATH};
+use super::controls::{ServiceConfig, ServiceState};
 
 pub fn register_methods<F>(
     state: &Arc<Mutex<ServiceState>>,
     event_handler: &Arc<Mutex<F>>,
-    friendly_name: String,
-    seeked_signal: SeekedSignal,
+    config: &ServiceConfig,
 ) -> Crossroads
 where
     F: Fn(MediaControlEvent) + Send + 'static,
 {
     let mut cr = Crossroads::new();
     let app_interface = cr.register("org.mpris.MediaPlayer2", {
+        let state = state.clone();
         let event_handler = event_handler.clone();
+        let (can_raise, can_quit) = (config.can_raise, config.can_quit);
+        let can_set_fullscreen = config.can_set_fullscreen;
+        let desktop_entry = config.desktop_entry.clone();
+        let supported_uri_schemes = config.supported_uri_schemes.clone();
+        let supported_mime_types = config.supported_mime_types.clone();
 
         move |b| {
             b.property("Identity")
-                .get(move |_, _| Ok(friendly_name.clone()));
+                .get({
+                    let state = state.clone();
+                    move |_, _| Ok(state.lock().unwrap().identity.clone())
+                })
+                .emits_changed_true();
+            if let Some(desktop_entry) = desktop_entry {
+                b.property("DesktopEntry")
+                    .get(move |_, _| Ok(desktop_entry.clone()))
+                    .emits_changed_const();
+            }
 
-            register_method(b, &event_handler, "Raise", MediaControlEvent::Raise);
-            register_method(b, &event_handler, "Quit", MediaControlEvent::Quit);
+            if can_raise {
+                register_method(b, &event_handler, "Raise", MediaControlEvent::Raise);
+            } else {
+                b.method("Raise", (), (), |_, _, _: ()| Ok(()));
+            }
+            if can_quit {
+                register_method(b, &event_handler, "Quit", MediaControlEvent::Quit);
+            } else {
+                b.method("Quit", (), (), |_, _, _: ()| Ok(()));
+            }
 
-            // TODO: allow user to set these properties
             b.property("CanQuit")
-                .get(|_, _| Ok(true))
+                .get(move |_, _| Ok(can_quit))
                 .emits_changed_true();
             b.property("CanRaise")
-                .get(|_, _| Ok(true))
+                .get(move |_, _| Ok(can_raise))
+                .emits_changed_true();
+            b.property("Fullscreen")
+                .get({
+                    let state = state.clone();
+                    move |_, _| Ok(state.lock().unwrap().fullscreen)
+                })
+                .set({
+                    let event_handler = event_handler.clone();
+                    move |_, _, fullscreen: bool| {
+                        if can_set_fullscreen {
+                            (event_handler.lock().unwrap())(MediaControlEvent::SetFullscreen(
+                                fullscreen,
+                            ));
+                        }
+                        // The application confirms the change by calling `set_fullscreen`.
+                        Ok(None)
+                    }
+                })
+                .emits_changed_true();
+            b.property("CanSetFullscreen")
+                .get(move |_, _| Ok(can_set_fullscreen))
                 .emits_changed_true();
-            b.property("HasTracklist")
+            b.property("HasTrackList")
                 .get(|_, _| Ok(false))
                 .emits_changed_true();
             b.property("SupportedUriSchemes")
-                .get(move |_, _| Ok(&[] as &[String]))
+                .get(move |_, _| Ok(supported_uri_schemes.clone()))
                 .emits_changed_true();
             b.property("SupportedMimeTypes")
-                .get(move |_, _| Ok(&[] as &[String]))
+                .get(move |_, _| Ok(supported_mime_types.clone()))
                 .emits_changed_true();
         }
     });
 
     let player_interface = cr.register("org.mpris.MediaPlayer2.Player", |b| {
-        register_method(b, event_handler, "Next", MediaControlEvent::Next);
-        register_method(b, event_handler, "Previous", MediaControlEvent::Previous);
-        register_method(b, event_handler, "Pause", MediaControlEvent::Pause);
-        register_method(b, event_handler, "PlayPause", MediaControlEvent::Toggle);
-        register_method(b, event_handler, "Stop", MediaControlEvent::Stop);
-        register_method(b, event_handler, "Play", MediaControlEvent::Play);
+        let mut method = |name, button, event| {
+            register_button_method(b, state, event_handler, name, button, event);
+        };
+        method("Next", MediaButton::Next, MediaControlEvent::Next);
+        method(
+            "Previous",
+            MediaButton::Previous,
+            MediaControlEvent::Previous,
+        );
+        method("Pause", MediaButton::Pause, MediaControlEvent::Pause);
+        // According to the MPRIS specification, PlayPause depends on CanPause.
+        method("PlayPause", MediaButton::Pause, MediaControlEvent::Toggle);
+        method("Stop", MediaButton::Stop, MediaControlEvent::Stop);
+        method("Play", MediaButton::Play, MediaControlEvent::Play);
 
         b.method("Seek", ("Offset",), (), {
+            let state = state.clone();
             let event_handler = event_handler.clone();
+            let read_only = config.read_only;
+            let suspended = config.suspended.clone();
+
+            move |_, _, (offset,): (i64,)| {
+                if read_only || suspended.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+                // According to the MPRIS specification, the call has no effect if CanControl
+                // or CanSeek is false.
+                let seekable = {
+                    let state = state.lock().unwrap();
+                    state.can_control && state.is_seekable()
+                };
+                if !seekable {
+                    return Ok(());
+                }
 
-            move |ctx, _, (offset,): (i64,)| {
                 let abs_offset = offset.unsigned_abs();
                 let direction = if offset > 0 {
                     SeekDirection::Forward
@@ -78,7 +146,7 @@ where
                     direction,
                     Duration::from_micros(abs_offset),
                 ));
-                ctx.push_msg(ctx.make_signal("Seeked", ()));
+                // `Seeked` is emitted by the service once the application sets the position.
                 Ok(())
             }
         });
@@ -87,16 +155,35 @@ where
             let state = state.clone();
             let event_handler = event_handler.clone();
 
-            move |_, _, (_trackid, position): (Path, i64)| {
-                let state = state.lock().unwrap();
+            move |_, _, (track_id, position): (Path, i64)| {
+                // The state is released before the event is delivered, as the handler may
+                // read it, e.g. with `MediaControls::get_metadata`.
+                let (seekable, is_current, duration) = {
+                    let state = state.lock().unwrap();
+                    let current_track_id = state.metadata.track_id.as_ref();
+                    let is_current = current_track_id.map_or(false, |id| id.as_ref() == &*track_id);
+                    (
+                        state.can_control && state.is_seekable(),
+                        is_current && state.has_track(),
+                        state.metadata.duration,
+                    )
+                };
 
                 // According to the MPRIS specification:
 
-                // TODO: If the TrackId argument is not the same as the current
-                // trackid, the call is ignored as stale.
-                // (Maybe it should be optional?)
+                // If CanControl or CanSeek is false, the call has no effect.
+                if !seekable {
+                    return Ok(());
+                }
+
+                // If the TrackId argument is not the same as the current
+                // trackid, the call is ignored as stale. There is no position to set
+                // without a track.
+                if !is_current {
+                    return Ok(());
+                }
 
-                if let Some(duration) = state.metadata.duration {
+                if let Some(duration) = duration {
                     // If the Position argument is greater than the track length, do nothing.
                     if position > duration {
                         return Ok(());
@@ -116,15 +203,46 @@ where
         });
 
         b.method("OpenUri", ("Uri",), (), {
+            let state = state.clone();
             let event_handler = event_handler.clone();
+            let metadata_transform = config.metadata_transform.clone();
+            // Clients can't change what a read-only player plays.
+            let open_uri_handler = config
+                .open_uri_handler
+                .clone()
+                .filter(|_| !config.read_only);
+            let suspended = config.suspended.clone();
+
+            move |ctx, _, (uri,): (String,)| {
+                if !state.lock().unwrap().can_control {
+                    return Ok(());
+                }
+                if let Some(metadata) = open_uri_handler
+                    .as_ref()
+                    .filter(|_| !suspended.load(Ordering::Relaxed))
+                    .and_then(|handler| handler.call(&uri))
+                {
+                    let mut state = state.lock().unwrap();
+                    state.set_metadata(metadata, metadata_transform.as_ref());
+
+                    let properties_changed = PropertiesPropertiesChanged {
+                        interface_name: "org.mpris.MediaPlayer2.Player".to_string(),
+                        changed_properties: HashMap::from([(
+                            "Metadata".to_string(),
+                            Variant(state.metadata_dict.box_clone()),
+                        )]),
+                        invalidated_properties: Vec::new(),
+                    };
+                    ctx.push_msg(properties_changed.to_emit_message(ctx.path()));
+                }
 
-            move |_, _, (uri,): (String,)| {
                 (event_handler.lock().unwrap())(MediaControlEvent::OpenUri(uri));
                 Ok(())
             }
         });
 
-        *seeked_signal.lock().unwrap() = Some(b.signal::<(String,), _>("Seeked", ("x",)).msg_fn());
+        // Only declared for introspection, as the service emits it on every path itself.
+        b.signal::<(i64,), _>("Seeked", ("Position",));
 
         b.property("PlaybackStatus")
             .get({
@@ -136,12 +254,79 @@ where
             })
             .emits_changed_true();
 
-        b.property("Rate").get(|_, _| Ok(1.0)).emits_changed_true();
+        b.property("Rate")
+            .get({
+                let state = state.clone();
+                move |_, _| Ok(state.lock().unwrap().rate)
+            })
+            .set({
+                let state = state.clone();
+                let event_handler = event_handler.clone();
+                let (minimum_rate, maximum_rate) = (config.minimum_rate, config.maximum_rate);
+                move |_, _, rate: f64| {
+                    if !state.lock().unwrap().can_control {
+                        return Ok(None);
+                    }
+                    let rate = rate.clamp(minimum_rate, maximum_rate);
+                    (event_handler.lock().unwrap())(MediaControlEvent::SetRate(rate));
+                    // The application confirms the change by calling `set_rate`.
+                    Ok(None)
+                }
+            })
+            .emits_changed_true();
+
+        b.property("LoopStatus")
+            .get({
+                let state = state.clone();
+                move |_, _| Ok(loop_status_name(state.lock().unwrap().loop_status).to_string())
+            })
+            .set({
+                let state = state.clone();
+                let event_handler = event_handler.clone();
+                move |_, _, loop_status: String| {
+                    if !state.lock().unwrap().can_control {
+                        return Ok(None);
+                    }
+                    let loop_status = parse_loop_status(&loop_status)
+                        .ok_or_else(|| MethodErr::invalid_arg(&loop_status))?;
+                    (event_handler.lock().unwrap())(MediaControlEvent::SetLoopStatus(loop_status));
+                    // The application confirms the change by calling `set_loop_status`.
+                    Ok(None)
+                }
+            })
+            .emits_changed_true();
+
+        b.property("Shuffle")
+            .get({
+                let state = state.clone();
+                move |_, _| Ok(state.lock().unwrap().shuffle)
+            })
+            .set({
+                let state = state.clone();
+                let event_handler = event_handler.clone();
+                move |_, _, shuffle: bool| {
+                    if !state.lock().unwrap().can_control {
+                        return Ok(None);
+                    }
+                    (event_handler.lock().unwrap())(MediaControlEvent::SetShuffle(shuffle));
+                    // The application confirms the change by calling `set_shuffle`.
+                    Ok(None)
+                }
+            })
+            .emits_changed_true();
 
         b.property("Metadata")
             .get({
                 let state = state.clone();
-                move |_, _| Ok(create_metadata_dict(&state.lock().unwrap().metadata))
+                move |_, _| {
+                    let state = state.lock().unwrap();
+                    let metadata_dict: HashMap<_, _> = state
+                        .metadata_dict
+                        .iter()
+                        .map(|(key, value)| (key.clone(), Variant(value.0.box_clone())))
+                        .collect();
+                    Ok(metadata_dict)
+                }
             })
             .emits_changed_true();
 
@@ -154,9 +339,28 @@ where
                 }
             })
             .set({
+                let state = state.clone();
                 let event_handler = event_handler.clone();
+                let echo_volume = config.echo_volume;
+                let volume_scale = config.volume_scale;
+                let suspended = config.suspended.clone();
                 move |_, _, volume: f64| {
-                    (event_handler.lock().unwrap())(MediaControlEvent::SetVolume(volume));
+                    if !state.lock().unwrap().can_control {
+                        return Ok(None);
+                    }
+                    (event_handler.lock().unwrap())(MediaControlEvent::SetVolume(
+                        volume_scale.convert(volume),
+                    ));
+
+                    if !echo_volume || suspended.load(Ordering::Relaxed) {
+                        // The application confirms the change by calling `set_volume`.
+                        return Ok(None);
+                    }
+
+                    // According to the MPRIS specification, a negative volume
+                    // should be treated as 0.0.
+                    let volume = volume.max(0.0);
+                    state.lock().unwrap().volume = volume;
                     Ok(Some(volume))
                 }
             })
@@ -166,26 +370,17 @@ where
             let state = state.clone();
             move |_, _| {
                 let state = state.lock().unwrap();
-                let progress: i64 = match state.playback_status {
-                    MediaPlayback::Playing {
-                        progress: Some(progress),
-                    }
-                    | MediaPlayback::Paused {
-                        progress: Some(progress),
-                    } => progress.0.as_micros(),
-                    _ => 0,
-                }
-                .try_into()
-                .unwrap();
-                Ok(progress)
+                Ok(to_micros(state.get_position()))
             }
         });
 
+        let minimum_rate = config.minimum_rate;
         b.property("MinimumRate")
-            .get(|_, _| Ok(1.0))
+            .get(move |_, _| Ok(minimum_rate))
             .emits_changed_true();
+        let maximum_rate = config.maximum_rate;
         b.property("MaximumRate")
-            .get(|_, _| Ok(1.0))
+            .get(move |_, _| Ok(maximum_rate))
             .emits_changed_true();
 
         b.property("CanGoNext")
@@ -215,25 +410,92 @@ where
         b.property("CanSeek")
             .get({
                 let state = state.clone();
-                move |_, _| Ok(state.lock().unwrap().can_seek)
+                move |_, _| Ok(state.lock().unwrap().is_seekable())
             })
             .emits_changed_true();
         b.property("CanControl")
-            .get(|_, _| Ok(true))
+            .get({
+                let state = state.clone();
+                move |_, _| Ok(state.lock().unwrap().can_control)
+            })
             .emits_changed_true();
     });
 
-    cr.insert(
-        "/org/mpris/MediaPlayer2",
-        &[app_interface, player_interface],
-        (),
-    );
+    let mut interfaces = vec![app_interface, player_interface];
+
+    // MPRIS metadata is read-only, so a settable rating needs its own interface.
+    if let Some(rating_interface) = &config.rating_interface {
+        interfaces.push(cr.register(rating_interface.clone(), |b| {
+            b.property("Rating")
+                .get({
+                    let state = state.clone();
+                    move |_, _| Ok(state.lock().unwrap().rating.unwrap_or(0.0))
+                })
+                .set({
+                    let state = state.clone();
+                    let event_handler = event_handler.clone();
+                    move |_, _, rating: f64| {
+                        if !state.lock().unwrap().can_control {
+                            return Ok(None);
+                        }
+                        // NaN is rejected too.
+                        if !(0.0..=1.0).contains(&rating) {
+                            return Err(MethodErr::invalid_arg(&rating));
+                        }
+                        (event_handler.lock().unwrap())(MediaControlEvent::SetRating(rating));
+                        // The application confirms the change by calling `set_rating`.
+                        Ok(None)
+                    }
+                })
+                .emits_changed_true();
+        }));
+    }
+
+    if config.serve_version_info {
+        let state = state.clone();
+        interfaces.push(cr.register("com.souvlaki.Info", move |b| {
+            b.property("Version")
+                .get(|_, _| Ok(env!("CARGO_PKG_VERSION").to_string()))
+                .emits_changed_const();
+            b.property("DisplayName")
+                .get(move |_, _| Ok(state.lock().unwrap().identity.clone()))
+                .emits_changed_true();
+        }));
+    }
+
+    if let Some(interfaces_hook) = &config.interfaces_hook {
+        interfaces.extend(interfaces_hook.call(&mut cr));
+    }
 
-    seeked_signal.lock().ok();
+    cr.insert(MPRIS_OBJECT_PATH, &interfaces, ());
+    for alias in &config.path_aliases {
+        cr.insert(alias.clone(), &interfaces, ());
+    }
 
     cr
 }
 
+/// Dispatch a method call to the methods registered on `cr`, returning the reply and the
+/// signals to send instead of sending them. This keeps the method logic independent of the
+/// connection, so that it can be run without a bus.
+pub fn dispatch(cr: &mut Crossroads, msg: Message) -> Result<Vec<Message>, ()> {
+    let outgoing = OutgoingMessages::default();
+    cr.handle_message(msg, &outgoing)?;
+    Ok(outgoing.0.into_inner())
+}
+
+/// Collects the messages Crossroads sends while handling a method call.
+#[derive(Default)]
+struct OutgoingMessages(RefCell<Vec<Message>>);
+
+impl Sender for OutgoingMessages {
+    fn send(&self, msg: Message) -> Result<u32, ()> {
+        self.0.borrow_mut().push(msg);
+        // The serial is assigned when the message is actually sent.
+        Ok(0)
+    }
+}
+
 fn register_method<F>(
     b: &mut IfaceBuilder<()>,
     event_handler: &Arc<Mutex<F>>,
@@ -249,3 +511,30 @@ fn register_method<F>(
         Ok(())
     });
 }
+
+/// Register a method delivering `event`, ignoring calls while `button` is disabled or
+/// clients can't control the player.
+fn register_button_method<F>(
+    b: &mut IfaceBuilder<()>,
+    state: &Arc<Mutex<ServiceState>>,
+    event_handler: &Arc<Mutex<F>>,
+    name: &'static str,
+    button: MediaButton,
+    event: MediaControlEvent,
+) where
+    F: Fn(MediaControlEvent) + Send + 'static,
+{
+    let state = state.clone();
+    let event_handler = event_handler.clone();
+
+    b.method(name, (), (), move |_, _, _: ()| {
+        let enabled = {
+            let state = state.lock().unwrap();
+            state.can_control && state.is_button_enabled(button) != Some(false)
+        };
+        if enabled {
+            (event_handler.lock().unwrap())(event.clone());
+        }
+        Ok(())
+    });
+}
@@ -1,4 +1,5 @@
 mod interfaces;
+mod waking_channel;
 
 mod controls;
-pub use controls::MediaControls;
+pub use controls::{MediaControls, OwnedMetadata, StopHandle};
@@ -0,0 +1,128 @@
+//! A channel like `std::sync::mpsc`, whose receiver can also wait for a file descriptor to
+//! become ready, so that the service sleeps until either an event is sent or the D-Bus
+//! connection is ready instead of waiting for each in turn.
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+/// Create a channel. The socket pair waking the receiver up is only created by
+/// [`Receiver::make_wakeable`], once the receiver is used, so that this can't fail.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let (tx, rx) = mpsc::channel();
+    let waker = Arc::new(Mutex::new(None));
+    let sender = Sender {
+        channel: tx,
+        waker: waker.clone(),
+    };
+    let receiver = Receiver {
+        channel: rx,
+        waker,
+        wakeups: None,
+    };
+    (sender, receiver)
+}
+
+#[derive(Clone, Debug)]
+pub struct Sender<T> {
+    channel: mpsc::Sender<T>,
+    waker: Arc<Mutex<Option<UnixStream>>>,
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, value: T) -> Result<(), mpsc::SendError<T>> {
+        self.channel.send(value)?;
+        // Without a waker, the receiver isn't waiting yet, and receives the value once it is.
+        if let Some(waker) = &*self.waker.lock().unwrap() {
+            (&*waker).write(&[0]).ok();
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Receiver<T> {
+    channel: mpsc::Receiver<T>,
+    waker: Arc<Mutex<Option<UnixStream>>>,
+    wakeups: Option<UnixStream>,
+}
+
+impl<T> Receiver<T> {
+    /// Create the socket pair waking the receiver up, which [`Receiver::wait`] needs to
+    /// return when a value is sent. Fails if the process is out of file descriptors.
+    pub fn make_wakeable(&mut self) -> io::Result<()> {
+        if self.wakeups.is_none() {
+            let (waker, wakeups) = UnixStream::pair()?;
+            // A full socket already wakes the receiver up, and it's only drained as far as
+            // possible.
+            waker.set_nonblocking(true).ok();
+            wakeups.set_nonblocking(true).ok();
+            *self.waker.lock().unwrap() = Some(waker);
+            self.wakeups = Some(wakeups);
+        }
+        Ok(())
+    }
+
+    pub fn try_recv(&self) -> Option<T> {
+        // Drain the wake-ups first, so that a value sent from now on wakes `wait` up again.
+        if let Some(mut wakeups) = self.wakeups.as_ref() {
+            let mut buf = [0; 64];
+            while matches!(wakeups.read(&mut buf), Ok(n) if n > 0) {}
+        }
+        self.channel.try_recv().ok()
+    }
+
+    /// Wait until a value is sent, or `None` once every sender is dropped.
+    pub fn recv(&self) -> Option<T> {
+        self.channel.recv().ok()
+    }
+
+    pub fn try_iter(&self) -> mpsc::TryIter<'_, T> {
+        self.channel.try_iter()
+    }
+
+    /// Wait until a value is sent, `fd` is ready for `events` or the timeout elapses.
+    /// Values sent since the last [`Receiver::try_recv`] return right away.
+    pub fn wait(&self, fd: RawFd, events: libc::c_short, timeout: Duration) {
+        let mut fds = [
+            libc::pollfd {
+                fd,
+                events,
+                revents: 0,
+            },
+            libc::pollfd {
+                // Negative file descriptors are ignored.
+                fd: self.wakeups.as_ref().map_or(-1, AsRawFd::as_raw_fd),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        // Round up, so that waiting less than a millisecond doesn't turn into spinning.
+        let timeout = (timeout.as_micros() + 999) / 1000;
+        let timeout = libc::c_int::try_from(timeout).unwrap_or(libc::c_int::MAX);
+        // Interruptions and errors only cut the wait short.
+        unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_sent_before_the_receiver_is_wakeable_are_received() {
+        let (sender, mut receiver) = channel();
+        sender.send(1).unwrap();
+        receiver.make_wakeable().unwrap();
+        sender.send(2).unwrap();
+
+        // The second value wakes the receiver up.
+        receiver.wait(-1, 0, Duration::from_secs(5));
+        assert_eq!(receiver.try_recv(), Some(1));
+        assert_eq!(receiver.try_recv(), Some(2));
+        assert_eq!(receiver.try_recv(), None);
+    }
+}
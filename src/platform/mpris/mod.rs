@@ -20,6 +20,483 @@ pub use self::dbus::*;
 #[cfg(feature = "dbus")]
 extern crate dbus as dbus_crate;
 
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::{
+    Capabilities, ImageFormat, LoopStatus, MediaButton, MediaControlEvent, MediaMetadata,
+    MediaPlayback, MediaPosition, SeekedEmission, TrackId,
+};
+
+/// The metadata key holding the message passed to `MediaControls::set_error`.
+const ERROR_METADATA_KEY: &str = "com.souvlaki:error";
+
+/// The metadata key holding the rates passed to `MediaControls::set_available_rates`.
+const AVAILABLE_RATES_METADATA_KEY: &str = "com.souvlaki:availableRates";
+
+/// The metadata key holding `MediaMetadata::chapters`.
+const CHAPTERS_METADATA_KEY: &str = "com.souvlaki:chapters";
+
+/// Whether to emit `Seeked` when the position clients expect is replaced by `position`.
+fn is_seek(emission: SeekedEmission, expected: Duration, position: Duration) -> bool {
+    match emission {
+        SeekedEmission::Never => false,
+        SeekedEmission::OnDiscontinuity(threshold) => {
+            let difference = if position > expected {
+                position - expected
+            } else {
+                expected - position
+            };
+            difference > threshold
+        }
+        SeekedEmission::Always => true,
+    }
+}
+
+/// The playback status with its position replaced, or `None` when stopped, as there's no
+/// position then.
+fn with_position(playback: &MediaPlayback, position: MediaPosition) -> Option<MediaPlayback> {
+    match playback {
+        MediaPlayback::Playing { .. } => Some(MediaPlayback::Playing {
+            progress: Some(position),
+        }),
+        MediaPlayback::Paused { .. } => Some(MediaPlayback::Paused {
+            progress: Some(position),
+        }),
+        MediaPlayback::Stopped => None,
+    }
+}
+
+/// Whether a button is enabled according to the capabilities served to clients, which are
+/// expected to disable every button, Stop included, while they can't control playback.
+fn is_served_enabled(capabilities: Capabilities, button: MediaButton) -> bool {
+    capabilities.can_control
+        && capabilities
+            .buttons()
+            .iter()
+            .any(|&(served, enabled)| served == button && enabled)
+}
+
+/// The value of the `LoopStatus` property for a loop status.
+fn loop_status_name(loop_status: LoopStatus) -> &'static str {
+    match loop_status {
+        LoopStatus::None => "None",
+        LoopStatus::Track => "Track",
+        LoopStatus::Playlist => "Playlist",
+    }
+}
+
+/// Parse the value of the `LoopStatus` property.
+fn parse_loop_status(name: &str) -> Option<LoopStatus> {
+    match name {
+        "None" => Some(LoopStatus::None),
+        "Track" => Some(LoopStatus::Track),
+        "Playlist" => Some(LoopStatus::Playlist),
+        _ => None,
+    }
+}
+
+/// Round a duration to the nearest second, rounding half up, or down past the longest
+/// whole number of seconds.
+fn round_to_seconds(duration: Duration) -> Duration {
+    let round_up = u64::from(duration.subsec_millis() >= 500);
+    Duration::from_secs(duration.as_secs().saturating_add(round_up))
+}
+
+/// Convert a duration to the `i64` microseconds used by MPRIS, saturating, as a
+/// [`Duration`] can be much longer.
+fn to_micros(duration: Duration) -> i64 {
+    i64::try_from(duration.as_micros()).unwrap_or(i64::MAX)
+}
+
+/// Convert a track or disc number to the `i32` of its xesam key, saturating.
+fn to_i32(number: u32) -> i32 {
+    i32::try_from(number).unwrap_or(i32::MAX)
+}
+
+/// Own the strings of a list field of [`crate::MediaMetadata`], which is empty if unset.
+fn to_owned_strings(strings: Option<Vec<&str>>) -> Vec<String> {
+    strings.into_iter().flatten().map(str::to_owned).collect()
+}
+
+/// Deliver an event to the application's handler, catching a panic in it rather than
+/// letting it unwind through the service, which would poison the handler's mutex and stop
+/// the service thread. The panic is still reported by the panic hook, and the next events
+/// are delivered as usual.
+fn deliver_event(event_handler: &dyn Fn(MediaControlEvent), event: MediaControlEvent) {
+    log::debug!("delivering {:?}", event);
+    if panic::catch_unwind(AssertUnwindSafe(|| event_handler(event))).is_err() {
+        log::warn!("the event handler panicked");
+    }
+}
+
+/// The prefix of the MPRIS bus name, followed by [`crate::PlatformConfig::dbus_name`].
+const BUS_NAME_PREFIX: &str = "org.mpris.MediaPlayer2.";
+
+/// The name of the service thread, to tell it apart in debuggers and panic messages.
+fn service_thread_name(dbus_name: &str) -> String {
+    format!("souvlaki-mpris-{}", dbus_name)
+}
+
+/// The object path MPRIS is served at, e.g. to emit signals from the same object.
+pub const MPRIS_OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+
+/// Check that `dbus_name` completes a valid MPRIS bus name, which the bus would
+/// otherwise reject with an obscure error on attaching.
+fn validate_bus_name(dbus_name: &str) -> Result<(), Error> {
+    // The whole bus name is limited to 255 bytes.
+    let is_valid = BUS_NAME_PREFIX.len() + dbus_name.len() <= 255
+        && dbus_name.split('.').all(|element| {
+            !element.is_empty()
+                && !element.starts_with(|c: char| c.is_ascii_digit())
+                && element
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        });
+    if is_valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidBusName(dbus_name.to_owned()))
+    }
+}
+
+/// The maximum size of a D-Bus message. The limit of the bus daemon can't be queried over
+/// the bus, so this is the default one of `dbus-daemon`, below the 128 MiB allowed by the
+/// specification.
+const MAX_MESSAGE_SIZE: usize = 32 * 1024 * 1024;
+
+/// Room left in a message for everything but the cover URL.
+const MESSAGE_OVERHEAD: usize = 64 * 1024;
+
+/// Replace a `data:` cover URL too large to be sent in a message of `max_message_size` by
+/// the URL of a temporary file holding the image. Other URLs are left as they are.
+fn fit_cover_url(cover_url: String, max_message_size: usize) -> String {
+    if cover_url.len() + MESSAGE_OVERHEAD <= max_message_size {
+        return cover_url;
+    }
+    decode_data_url(&cover_url)
+        .and_then(|(bytes, extension)| crate::cover_file_url(&bytes, extension).ok())
+        .unwrap_or(cover_url)
+}
+
+/// Decode a base64 `data:` URL of an image, returning its bytes and a file extension.
+fn decode_data_url(url: &str) -> Option<(Vec<u8>, &str)> {
+    use base64::Engine;
+
+    let (header, data) = url.strip_prefix("data:")?.split_once(',')?;
+    let mime_type = header.strip_suffix(";base64")?;
+    // E.g. "png" for "image/png", "svg" for "image/svg+xml".
+    let subtype = mime_type.strip_prefix("image/")?;
+    let extension = subtype.split('+').next()?;
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .ok()?;
+    Some((bytes, extension))
+}
+
+/// A file holding [`crate::MediaMetadata::cover_bytes`], removed when dropped.
+#[derive(Debug)]
+struct CoverFile(PathBuf);
+
+impl CoverFile {
+    /// Write the bytes to a new file in `$XDG_RUNTIME_DIR`, or the temporary directory if
+    /// unset. Every file gets a new name, so that clients caching the art by URL reload it.
+    fn write(format: ImageFormat, bytes: &[u8]) -> std::io::Result<Self> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        let path = dir.join(format!(
+            "souvlaki-cover-{}-{}.{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+            format.extension()
+        ));
+        std::fs::write(&path, bytes)?;
+        Ok(Self(path))
+    }
+
+    fn url(&self) -> String {
+        format!("file://{}", self.0.display())
+    }
+}
+
+impl Drop for CoverFile {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.0).ok();
+    }
+}
+
+/// What the setters keep between calls, shared by `MediaControls` and its handles.
+#[derive(Debug, Default)]
+struct SetterState {
+    /// The buttons disabled since attaching, kept here to be read back without waiting
+    /// for the service thread.
+    disabled_buttons: HashSet<MediaButton>,
+    /// The number of track ids generated for metadata set without one.
+    generated_track_ids: u64,
+    /// The track id generated for the current media item, and what identifies the item.
+    generated_track_id: Option<(ItemKey, TrackId)>,
+    /// The file written for the cover bytes of the current metadata.
+    cover_file: Option<CoverFile>,
+}
+
+/// What tells media items set without a track id apart, so that their generated track id
+/// stays the same when only e.g. the cover or the duration of the item changes.
+#[derive(PartialEq, Eq, Debug)]
+struct ItemKey {
+    title: Option<String>,
+    album: Option<String>,
+    artist: Option<String>,
+    artists: Vec<String>,
+    track_number: Option<u32>,
+    disc_number: Option<u32>,
+}
+
+impl ItemKey {
+    fn new(metadata: &OwnedMetadata) -> Self {
+        Self {
+            title: metadata.title.clone(),
+            album: metadata.album.clone(),
+            artist: metadata.artist.clone(),
+            artists: metadata.artists.clone(),
+            track_number: metadata.track_number,
+            disc_number: metadata.disc_number,
+        }
+    }
+}
+
+impl SetterState {
+    fn record_button_enabled(&mut self, button: MediaButton, enabled: bool) {
+        if enabled {
+            self.disabled_buttons.remove(&button);
+        } else {
+            self.disabled_buttons.insert(button);
+        }
+    }
+
+    /// Prepare metadata to be sent to the service, as configured.
+    /// If `merged`, the metadata is merged into the current one, which keeps its track id and
+    /// cover unless they are set.
+    fn to_owned_metadata(
+        &mut self,
+        mut metadata: MediaMetadata,
+        merged: bool,
+        round_duration_to_seconds: bool,
+        max_message_size: Option<usize>,
+    ) -> OwnedMetadata {
+        if round_duration_to_seconds {
+            metadata.duration = metadata.duration.map(round_to_seconds);
+        }
+        // Empty metadata, e.g. when clearing it, is no track at all.
+        let needs_track_id =
+            !merged && metadata.track_id.is_none() && metadata != Default::default();
+        let has_cover = metadata.cover_url.is_some() || metadata.cover_bytes.is_some();
+        if !merged || has_cover {
+            // Replacing the previous file removes it. If the bytes can't be written, there's
+            // no cover, as when a URL can't be loaded.
+            self.cover_file = match (metadata.cover_url, metadata.cover_bytes) {
+                (None, Some((format, bytes))) => CoverFile::write(format, bytes).ok(),
+                _ => None,
+            };
+        }
+        let mut metadata: OwnedMetadata = metadata.into();
+        if let Some(cover_file) = self.cover_file.as_ref().filter(|_| !merged || has_cover) {
+            metadata.cover_url = Some(cover_file.url());
+        }
+        if needs_track_id {
+            // The same item keeps its id, so that clients don't take it for a new track.
+            let key = ItemKey::new(&metadata);
+            let track_id = match self.generated_track_id.take() {
+                Some((previous, track_id)) if previous == key => track_id,
+                _ => {
+                    self.generated_track_ids += 1;
+                    let path = format!("/org/souvlaki/track/{}", self.generated_track_ids);
+                    // Always a valid object path.
+                    TrackId::new(&path).unwrap()
+                }
+            };
+            metadata.track_id = Some(track_id.clone());
+            self.generated_track_id = Some((key, track_id));
+        } else if !merged {
+            self.generated_track_id = None;
+        }
+        if let Some(max_message_size) = max_message_size {
+            metadata.cover_url = metadata
+                .cover_url
+                .map(|cover_url| fit_cover_url(cover_url, max_message_size));
+        }
+        metadata
+    }
+}
+
+impl OwnedMetadata {
+    /// Borrow the metadata as [`MediaMetadata`], e.g. to pass metadata built with
+    /// [`MediaMetadataBuilder`] to `MediaControls::set_metadata`. The error isn't included.
+    pub fn as_metadata(&self) -> MediaMetadata<'_> {
+        fn as_strs(strings: &[String]) -> Option<Vec<&str>> {
+            if strings.is_empty() {
+                return None;
+            }
+            Some(strings.iter().map(String::as_str).collect())
+        }
+
+        MediaMetadata {
+            title: self.title.as_deref(),
+            album: self.album.as_deref(),
+            artist: self.artist.as_deref(),
+            artists: as_strs(&self.artists),
+            album_artists: as_strs(&self.album_artists),
+            track_number: self.track_number,
+            disc_number: self.disc_number,
+            genre: as_strs(&self.genre),
+            content_created: self.content_created.as_deref(),
+            cover_url: self.cover_url.as_deref(),
+            cover_bytes: None,
+            duration: self
+                .duration
+                .and_then(|micros| u64::try_from(micros).ok())
+                .map(Duration::from_micros),
+            live: self.live,
+            track_id: self.track_id.as_ref(),
+            extra: if self.extra.is_empty() {
+                None
+            } else {
+                Some(
+                    self.extra
+                        .iter()
+                        .map(|(key, value)| (key.as_str(), value.clone()))
+                        .collect(),
+                )
+            },
+            chapters: if self.chapters.is_empty() {
+                None
+            } else {
+                Some(
+                    self.chapters
+                        .iter()
+                        .map(|(offset, title)| {
+                            let offset = u64::try_from(*offset).unwrap_or(0);
+                            (Duration::from_micros(offset), title.as_str())
+                        })
+                        .collect(),
+                )
+            },
+        }
+    }
+
+    /// Replace the fields set in `other`, keeping the others. `live` is only merged when
+    /// true, as it can't be unset.
+    fn merge(&mut self, other: OwnedMetadata) {
+        fn merge_strings(strings: &mut Vec<String>, other: Vec<String>) {
+            if !other.is_empty() {
+                *strings = other;
+            }
+        }
+
+        self.title = other.title.or(self.title.take());
+        self.album = other.album.or(self.album.take());
+        self.artist = other.artist.or(self.artist.take());
+        merge_strings(&mut self.artists, other.artists);
+        merge_strings(&mut self.album_artists, other.album_artists);
+        self.track_number = other.track_number.or(self.track_number);
+        self.disc_number = other.disc_number.or(self.disc_number);
+        merge_strings(&mut self.genre, other.genre);
+        self.content_created = other.content_created.or(self.content_created.take());
+        self.cover_url = other.cover_url.or(self.cover_url.take());
+        self.duration = other.duration.or(self.duration);
+        self.live |= other.live;
+        if self.live {
+            self.duration = None;
+        }
+        self.track_id = other.track_id.or(self.track_id.take());
+        if !other.chapters.is_empty() {
+            self.chapters = other.chapters;
+        }
+        self.extra.extend(other.extra);
+    }
+}
+
+/// A builder of [`OwnedMetadata`] from owned data, sparing the temporary bindings needed to
+/// borrow them into a [`MediaMetadata`]. Pass the result to `MediaControls::set_metadata`
+/// with [`OwnedMetadata::as_metadata`].
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct MediaMetadataBuilder(OwnedMetadata);
+
+impl MediaMetadataBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn title(mut self, title: String) -> Self {
+        self.0.title = Some(title);
+        self
+    }
+
+    pub fn artist(mut self, artist: String) -> Self {
+        self.0.artist = Some(artist);
+        self
+    }
+
+    pub fn album(mut self, album: String) -> Self {
+        self.0.album = Some(album);
+        self
+    }
+
+    pub fn cover_url(mut self, cover_url: String) -> Self {
+        self.0.cover_url = Some(cover_url);
+        self
+    }
+
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.0.duration = Some(to_micros(duration));
+        self
+    }
+
+    pub fn build(self) -> OwnedMetadata {
+        self.0
+    }
+}
+
+/// A function transforming the metadata before it is served, see
+/// `MediaControls::set_metadata_transform`.
+#[derive(Clone)]
+pub struct MetadataTransform(std::sync::Arc<dyn Fn(OwnedMetadata) -> OwnedMetadata + Send + Sync>);
+
+impl MetadataTransform {
+    fn apply(&self, metadata: OwnedMetadata) -> OwnedMetadata {
+        (self.0)(metadata)
+    }
+}
+
+impl std::fmt::Debug for MetadataTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MetadataTransform")
+    }
+}
+
+/// A function deriving the metadata to publish right away from a URI clients ask to
+/// open, see `MediaControls::on_open_uri`.
+#[derive(Clone)]
+pub struct OpenUriHandler(std::sync::Arc<dyn Fn(&str) -> Option<OwnedMetadata> + Send + Sync>);
+
+impl OpenUriHandler {
+    fn call(&self, uri: &str) -> Option<OwnedMetadata> {
+        (self.0)(uri)
+    }
+}
+
+impl std::fmt::Debug for OpenUriHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("OpenUriHandler")
+    }
+}
+
 /// A platform-specific error.
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -37,4 +514,118 @@ pub enum Error {
     // to at least be able to catch it, but it is preferable to have this thread *not panic* at all.
     #[error("D-Bus service thread panicked")]
     ThreadPanicked,
+    /// The OS refused to create the service thread, e.g. for lack of resources.
+    #[error("failed to spawn the D-Bus service thread: {0}")]
+    ThreadSpawnFailed(std::io::Error),
+    #[error(
+        "invalid D-Bus name {0:?}: each dot-separated element must be non-empty, must not start \
+         with a digit and may only contain ASCII letters, digits, '_' and '-'"
+    )]
+    InvalidBusName(String),
+    #[error("the MPRIS bus name is already owned by another connection")]
+    NameAlreadyOwned,
+    #[error("invalid volume {0}: must be a finite number")]
+    InvalidVolume(f64),
+    /// There's no D-Bus bus to connect to: no session bus, e.g. on a headless machine, or no
+    /// system bus with [`crate::BusType::System`]. Applications may skip media controls
+    /// altogether then.
+    #[error("no D-Bus bus to connect to: {0}")]
+    NoBus(String),
+    /// The socket waking the service up when a change is made couldn't be created, e.g.
+    /// because the process is out of file descriptors.
+    #[error("failed to create the socket waking the D-Bus service up: {0}")]
+    #[cfg(feature = "dbus")]
+    WakerFailed(std::io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn generated_track_id(setter_state: &mut SetterState, metadata: MediaMetadata) -> TrackId {
+        let metadata = setter_state.to_owned_metadata(metadata, false, false, None);
+        metadata.track_id.unwrap()
+    }
+
+    #[test]
+    fn generated_track_id_is_kept_for_the_same_item() {
+        let mut setter_state = SetterState::default();
+        let song = MediaMetadata {
+            title: Some("Song"),
+            ..Default::default()
+        };
+        let track_id = generated_track_id(&mut setter_state, song.clone());
+
+        let with_cover = MediaMetadata {
+            cover_url: Some("file:///cover.png"),
+            ..song.clone()
+        };
+        assert_eq!(generated_track_id(&mut setter_state, with_cover), track_id);
+
+        let other = MediaMetadata {
+            title: Some("Other"),
+            ..Default::default()
+        };
+        assert_ne!(generated_track_id(&mut setter_state, other), track_id);
+        // Another item was set in between, so this is played again.
+        assert_ne!(generated_track_id(&mut setter_state, song), track_id);
+    }
+
+    #[test]
+    fn events_are_delivered_after_a_handler_panic() {
+        let delivered = Mutex::new(Vec::new());
+        // Held in a mutex as by the services, which must not be poisoned by the panic.
+        let event_handler = Mutex::new(|event: MediaControlEvent| {
+            if event == MediaControlEvent::Quit {
+                panic!("the handler failed");
+            }
+            delivered.lock().unwrap().push(event);
+        });
+
+        for event in [
+            MediaControlEvent::Play,
+            MediaControlEvent::Quit,
+            MediaControlEvent::Pause,
+        ] {
+            deliver_event(&*event_handler.lock().unwrap(), event);
+        }
+        assert_eq!(
+            *delivered.lock().unwrap(),
+            [MediaControlEvent::Play, MediaControlEvent::Pause]
+        );
+    }
+
+    #[test]
+    fn no_button_is_served_enabled_without_control() {
+        let capabilities = Capabilities {
+            can_seek: false,
+            ..Default::default()
+        };
+        assert!(is_served_enabled(capabilities, MediaButton::Stop));
+        assert!(!is_served_enabled(capabilities, MediaButton::Seek));
+
+        let capabilities = Capabilities {
+            can_control: false,
+            ..capabilities
+        };
+        assert!(!is_served_enabled(capabilities, MediaButton::Play));
+        assert!(!is_served_enabled(capabilities, MediaButton::Stop));
+    }
+
+    #[test]
+    fn durations_are_rounded_to_seconds() {
+        assert_eq!(
+            round_to_seconds(Duration::from_millis(1499)),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            round_to_seconds(Duration::from_millis(1500)),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            round_to_seconds(Duration::MAX),
+            Duration::from_secs(u64::MAX)
+        );
+    }
 }
@@ -2,25 +2,116 @@ use std::collections::HashMap;
 use std::convert::From;
 use std::convert::TryFrom;
 use std::convert::TryInto;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use zbus::names::InterfaceName;
 use zbus::{dbus_interface, ConnectionBuilder, SignalContext};
 use zvariant::{ObjectPath, Value};
 
 use crate::{
-    MediaButton, MediaControlEvent, MediaMetadata, MediaPlayback, MediaPosition, PlatformConfig,
-    SeekDirection,
+    BusType, Capabilities, Clock, LoopStatus, MediaButton, MediaControlEvent, MediaMetadata,
+    MediaPlayback, MediaPosition, MetadataValue, PlatformConfig, SeekDirection, SeekedEmission,
+    SystemClock, TrackId, VolumeScale,
 };
 
-use super::Error;
+use super::{
+    deliver_event, is_seek, is_served_enabled, loop_status_name, parse_loop_status,
+    round_to_seconds, service_thread_name, to_i32, to_micros, to_owned_strings, validate_bus_name,
+    with_position, Error, MetadataTransform, OpenUriHandler, SetterState,
+    AVAILABLE_RATES_METADATA_KEY, BUS_NAME_PREFIX, CHAPTERS_METADATA_KEY, ERROR_METADATA_KEY,
+    MAX_MESSAGE_SIZE, MPRIS_OBJECT_PATH,
+};
 
 /// A handle to OS media controls.
 pub struct MediaControls {
     thread: Option<ServiceThreadHandle>,
     dbus_name: String,
-    friendly_name: String,
+    /// The well-known name owned by the current service.
+    bus_name: Option<String>,
+    config: ServiceConfig,
+    last_error: Arc<Mutex<Option<Error>>>,
+    stop_channel: Option<(mpsc::Sender<InternalEvent>, mpsc::Receiver<InternalEvent>)>,
+    /// What the setters keep between calls, shared with the [`MediaControlsHandle`]s.
+    setter_state: Arc<Mutex<SetterState>>,
+    /// The events queued by the setters within [`MediaControls::update`].
+    batch: Option<Vec<InternalEvent>>,
+    /// Whether to do nothing at all, see [`PlatformConfig::dummy`].
+    dummy: bool,
+}
+
+/// A handle to stop the service of [`MediaControls::attach`] or
+/// [`MediaControls::run_blocking`], e.g. from the event handler or another thread.
+#[derive(Clone, Debug)]
+pub struct StopHandle(mpsc::Sender<InternalEvent>);
+
+impl StopHandle {
+    /// Make the service return from [`MediaControls::run_blocking`], or end the service
+    /// thread of [`MediaControls::attach`]. The controls stay attached until
+    /// [`MediaControls::detach`] is called, which then joins the ended thread.
+    pub fn stop(&self) {
+        // If the service isn't running anymore there's nothing to stop.
+        self.0.send(InternalEvent::Kill).ok();
+    }
+}
+
+/// A handle to set the state of [`MediaControls`] from another thread, e.g. a decoder
+/// thread while the UI thread owns the controls. Clone it for every thread: its setters
+/// take `&self`, so no mutex is needed. Get one with [`MediaControls::handle`].
+#[derive(Clone, Debug)]
+pub struct MediaControlsHandle {
+    /// `None` in [`PlatformConfig::dummy`] mode, where the setters do nothing.
+    event_channel: Option<mpsc::Sender<InternalEvent>>,
+    setter_state: Arc<Mutex<SetterState>>,
+    round_duration_to_seconds: bool,
+}
+
+impl MediaControlsHandle {
+    /// Set the metadata of the currently playing media item, as with
+    /// [`MediaControls::set_metadata`].
+    pub fn set_metadata(&self, metadata: MediaMetadata) -> Result<(), Error> {
+        let metadata = self.setter_state.lock().unwrap().to_owned_metadata(
+            metadata,
+            false,
+            self.round_duration_to_seconds,
+            Some(MAX_MESSAGE_SIZE),
+        );
+        self.send_internal_event(InternalEvent::ChangeMetadata(metadata))
+    }
+
+    /// Set the current playback status, as with [`MediaControls::set_playback`].
+    pub fn set_playback(&self, playback: MediaPlayback) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangePlayback(playback))
+    }
+
+    /// Set the volume level, as with [`MediaControls::set_volume`].
+    pub fn set_volume(&self, volume: f64) -> Result<(), Error> {
+        if !volume.is_finite() {
+            return Err(Error::InvalidVolume(volume));
+        }
+        self.send_internal_event(InternalEvent::ChangeVolume(volume.max(0.0)))
+    }
+
+    /// Enable or disable a specific media control button, as with
+    /// [`MediaControls::set_button_enabled`].
+    pub fn set_button_enabled(&self, button: MediaButton, enabled: bool) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeButtonEnabled(button, enabled))?;
+        let mut setter_state = self.setter_state.lock().unwrap();
+        setter_state.record_button_enabled(button, enabled);
+        Ok(())
+    }
+
+    fn send_internal_event(&self, event: InternalEvent) -> Result<(), Error> {
+        match &self.event_channel {
+            // The channel is disconnected once the controls are detached.
+            Some(event_channel) => event_channel
+                .send(event)
+                .map_err(|_| Error::ThreadNotRunning),
+            None => Ok(()),
+        }
+    }
 }
 
 struct ServiceThreadHandle {
@@ -28,34 +119,234 @@ struct ServiceThreadHandle {
     thread: JoinHandle<()>,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, Debug)]
 enum InternalEvent {
     ChangeMetadata(OwnedMetadata),
+    MergeMetadata(OwnedMetadata),
+    ChangeDuration(Option<i64>),
+    ChangeNowPlaying(OwnedMetadata, MediaPlayback),
     ChangePlayback(MediaPlayback),
+    SetPosition(MediaPosition),
+    ChangeProgress(MediaPosition),
     ChangeVolume(f64),
+    ChangeRate(f64),
+    ChangeLoopStatus(LoopStatus),
+    ChangeShuffle(bool),
+    ChangeAvailableRates(Vec<f64>),
+    ChangeRating(f64),
     ChangeButtonEnabled(MediaButton, bool),
+    ChangeCanControl(bool),
+    ChangeIdentity(String),
+    ChangeFullscreen(bool),
+    Error(String),
+    TrackEnded,
+    FlashMessage(String, Duration),
+    EndFlash,
+    /// Request a copy of the state, sent back once the pending events are handled.
+    GetState(mpsc::Sender<ServiceState>),
+    /// Events queued together with [`MediaControls::update`].
+    Batch(Vec<InternalEvent>),
     Kill,
 }
 
+/// Settings the service is run with, taken from the [`PlatformConfig`].
+#[derive(Clone, Debug)]
+struct ServiceConfig {
+    friendly_name: String,
+    dbus_name_pid_suffix: bool,
+    bus: BusType,
+    echo_volume: bool,
+    read_only: bool,
+    volume_scale: VolumeScale,
+    seeked_emission: SeekedEmission,
+    seeked_interval: Option<Duration>,
+    poll_interval: Duration,
+    serve_version_info: bool,
+    round_duration_to_seconds: bool,
+    clamp_position: bool,
+    seeked_on_track_change: bool,
+    initial_volume: f64,
+    minimum_rate: f64,
+    maximum_rate: f64,
+    supported_uri_schemes: Vec<String>,
+    supported_mime_types: Vec<String>,
+    can_raise: bool,
+    can_quit: bool,
+    can_set_fullscreen: bool,
+    desktop_entry: Option<String>,
+    metadata_transform: Option<MetadataTransform>,
+    open_uri_handler: Option<OpenUriHandler>,
+    clock: Arc<dyn Clock>,
+    /// Shared with the service, see [`MediaControls::suspend`].
+    suspended: Arc<AtomicBool>,
+}
+
 #[derive(Clone, Debug)]
 struct ServiceState {
+    /// The display name, served as `Identity`.
+    identity: String,
+    fullscreen: bool,
     metadata: OwnedMetadata,
     playback_status: MediaPlayback,
+    /// When the playback status was last changed, according to the clock.
+    playback_updated_at: Instant,
+    clock: Arc<dyn Clock>,
+    /// Whether to clamp the position to the duration, see `PlatformConfig::clamp_position`.
+    clamp_position: bool,
+    /// See `PlatformConfig::seeked_on_track_change`.
+    seeked_on_track_change: bool,
     volume: f64,
+    read_only: bool,
+    seeked_emission: SeekedEmission,
     can_play: bool,
     can_pause: bool,
     can_go_next: bool,
     can_go_previous: bool,
     can_seek: bool,
+    can_control: bool,
+    rate: f64,
+    loop_status: LoopStatus,
+    shuffle: bool,
+    /// The rates advertised under the `com.souvlaki:availableRates` metadata key.
+    available_rates: Vec<f64>,
+    /// Set by `MediaControls::set_rating`, and served as `xesam:userRating`.
+    rating: Option<f64>,
+    /// A message shown as the title in place of the metadata's, and until when.
+    flash: Option<(String, Instant)>,
 }
 
+impl ServiceState {
+    /// Whether a button is enabled, or `None` for `MediaButton::Stop`, which has no
+    /// property of its own.
+    fn is_button_enabled(&self, button: MediaButton) -> Option<bool> {
+        match button {
+            MediaButton::Play => Some(self.can_play),
+            MediaButton::Pause => Some(self.can_pause),
+            MediaButton::Next => Some(self.can_go_next),
+            MediaButton::Previous => Some(self.can_go_previous),
+            MediaButton::Seek => Some(self.can_seek),
+            MediaButton::Stop => None,
+        }
+    }
+
+    /// Whether seeking is enabled and possible in the current media item.
+    fn is_seekable(&self) -> bool {
+        self.can_seek && !self.metadata.live
+    }
+
+    /// The capabilities served as the `Can*` properties.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_play: self.can_play,
+            can_pause: self.can_pause,
+            // There is no `CanStop` property.
+            can_stop: true,
+            can_go_next: self.can_go_next,
+            can_go_previous: self.can_go_previous,
+            can_seek: self.is_seekable(),
+            can_control: self.can_control,
+        }
+    }
+
+    fn set_playback_status(&mut self, playback: MediaPlayback) {
+        self.playback_status = playback;
+        self.playback_updated_at = self.clock.now();
+    }
+
+    /// When to emit `Seeked` next while playing, given the `seeked_interval` and when
+    /// it was last emitted.
+    fn next_seeked_at(
+        &self,
+        interval: Option<Duration>,
+        last_seeked_at: Instant,
+    ) -> Option<Instant> {
+        match (interval, &self.playback_status) {
+            // Clients read the position anew along with the playback status.
+            (Some(interval), MediaPlayback::Playing { .. }) => {
+                Some(last_seeked_at.max(self.playback_updated_at) + interval)
+            }
+            _ => None,
+        }
+    }
+
+    /// Change the playback rate, keeping the position reached so far at the previous one.
+    fn set_rate(&mut self, rate: f64) {
+        if let MediaPlayback::Playing { progress: Some(_) } = self.playback_status {
+            let position = MediaPosition(self.get_position());
+            self.set_playback_status(MediaPlayback::Playing {
+                progress: Some(position),
+            });
+        }
+        self.rate = rate;
+    }
+
+    /// Whether a media item is set, i.e. its track id isn't [`TrackId::no_track`].
+    fn has_track(&self) -> bool {
+        self.metadata
+            .track_id
+            .as_ref()
+            .map_or(false, |track_id| !track_id.is_no_track())
+    }
+
+    /// The position of the current media item, advanced by the time elapsed since
+    /// it was set, at the playback rate, if playing.
+    fn get_position(&self) -> Duration {
+        // Without a track, MPRIS clients expect no position.
+        if !self.has_track() {
+            return Duration::ZERO;
+        }
+        let position = match self.playback_status {
+            MediaPlayback::Playing {
+                progress: Some(progress),
+            } => {
+                let elapsed = self
+                    .clock
+                    .now()
+                    .saturating_duration_since(self.playback_updated_at);
+                progress.0
+                    + Duration::try_from_secs_f64(elapsed.as_secs_f64() * self.rate)
+                        .unwrap_or_default()
+            }
+            MediaPlayback::Paused {
+                progress: Some(progress),
+            } => progress.0,
+            _ => Duration::ZERO,
+        };
+        match self.metadata.duration {
+            Some(duration) if self.clamp_position => {
+                position.min(Duration::from_micros(u64::try_from(duration).unwrap_or(0)))
+            }
+            _ => position,
+        }
+    }
+}
+
+/// An owned version of [`MediaMetadata`], as passed to
+/// [`MediaControls::set_metadata_transform`].
 #[derive(Clone, PartialEq, Eq, Debug, Default)]
-struct OwnedMetadata {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OwnedMetadata {
     pub title: Option<String>,
     pub album: Option<String>,
     pub artist: Option<String>,
+    /// Every credited artist. If empty, `artist` is served alone.
+    pub artists: Vec<String>,
+    pub album_artists: Vec<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub genre: Vec<String>,
+    pub content_created: Option<String>,
     pub cover_url: Option<String>,
+    /// The duration in microseconds. Always `None` for live streams.
     pub duration: Option<i64>,
+    pub live: bool,
+    pub track_id: Option<TrackId>,
+    /// Set by `MediaControls::set_error`.
+    pub error: Option<String>,
+    /// The chapters as offsets in microseconds and titles.
+    pub chapters: Vec<(i64, String)>,
+    /// Additional metadata keys, added to the served metadata.
+    pub extra: HashMap<String, MetadataValue>,
 }
 
 impl From<MediaMetadata<'_>> for OwnedMetadata {
@@ -63,9 +354,30 @@ impl From<MediaMetadata<'_>> for OwnedMetadata {
         OwnedMetadata {
             title: other.title.map(|s| s.to_string()),
             artist: other.artist.map(|s| s.to_string()),
+            artists: to_owned_strings(other.artists),
+            album_artists: to_owned_strings(other.album_artists),
+            track_number: other.track_number,
+            disc_number: other.disc_number,
+            genre: to_owned_strings(other.genre),
+            content_created: other.content_created.map(|s| s.to_string()),
             album: other.album.map(|s| s.to_string()),
             cover_url: other.cover_url.map(|s| s.to_string()),
-            duration: other.duration.map(|d| d.as_micros().try_into().unwrap()),
+            duration: other.duration.filter(|_| !other.live).map(to_micros),
+            live: other.live,
+            track_id: other.track_id.cloned(),
+            error: None,
+            chapters: other
+                .chapters
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(offset, title)| (to_micros(offset), title.to_string()))
+                .collect(),
+            extra: other
+                .extra
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
         }
     }
 }
@@ -76,38 +388,215 @@ impl MediaControls {
         let PlatformConfig {
             dbus_name,
             display_name,
+            dbus_name_pid_suffix,
+            bus,
+            echo_volume,
+            read_only,
+            seeked_emission,
+            seeked_interval,
+            poll_interval,
+            serve_version_info,
+            round_duration_to_seconds,
+            clamp_position,
+            seeked_on_track_change,
+            initial_volume,
+            minimum_rate,
+            maximum_rate,
+            supported_uri_schemes,
+            supported_mime_types,
+            can_raise,
+            can_quit,
+            can_set_fullscreen,
+            desktop_entry,
+            dummy,
             ..
         } = config;
+        validate_bus_name(dbus_name)?;
 
         Ok(Self {
             thread: None,
             dbus_name: dbus_name.to_string(),
-            friendly_name: display_name.to_string(),
+            bus_name: None,
+            config: ServiceConfig {
+                friendly_name: display_name.to_string(),
+                dbus_name_pid_suffix,
+                bus,
+                echo_volume: echo_volume && !read_only,
+                read_only,
+                volume_scale: VolumeScale::Linear,
+                seeked_emission,
+                seeked_interval,
+                poll_interval: poll_interval.max(Duration::from_millis(1)),
+                serve_version_info,
+                round_duration_to_seconds,
+                clamp_position,
+                seeked_on_track_change,
+                initial_volume: initial_volume.clamp(0.0, 1.0),
+                // The MPRIS spec requires the range to include the normal rate.
+                minimum_rate: minimum_rate.min(1.0),
+                maximum_rate: maximum_rate.max(1.0),
+                supported_uri_schemes: supported_uri_schemes
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect(),
+                supported_mime_types: supported_mime_types.iter().map(|s| s.to_string()).collect(),
+                can_raise: can_raise && !read_only,
+                can_quit: can_quit && !read_only,
+                can_set_fullscreen: can_set_fullscreen && !read_only,
+                desktop_entry: desktop_entry.map(|s| s.to_string()),
+                metadata_transform: None,
+                open_uri_handler: None,
+                clock: Arc::new(SystemClock),
+                suspended: Arc::new(AtomicBool::new(false)),
+            },
+            last_error: Arc::new(Mutex::new(None)),
+            stop_channel: None,
+            setter_state: Default::default(),
+            batch: None,
+            dummy,
         })
     }
 
     /// Attach the media control events to a handler.
+    ///
+    /// Attaching again replaces the previous handler. Returns once the service thread is
+    /// ready to answer clients, or with the error it failed to start with.
     pub fn attach<F>(&mut self, event_handler: F) -> Result<(), Error>
     where
         F: Fn(MediaControlEvent) + Send + 'static,
     {
         self.detach()?;
+        if self.dummy {
+            return Ok(());
+        }
 
         let dbus_name = self.dbus_name.clone();
-        let friendly_name = self.friendly_name.clone();
+        let config = self.config.clone();
+        let last_error = self.last_error.clone();
         let event_handler = Arc::new(Mutex::new(event_handler));
-        let (event_channel, rx) = mpsc::channel();
-
+        let (event_channel, rx) = self.stop_channel.take().unwrap_or_else(mpsc::channel);
+        let (ready_sender, ready) = mpsc::channel();
+        self.setter_state.lock().unwrap().disabled_buttons.clear();
+
+        let thread = thread::Builder::new()
+            .name(service_thread_name(&dbus_name))
+            .spawn(move || {
+                if let Err(err) = pollster::block_on(run_service(
+                    dbus_name,
+                    config,
+                    event_handler,
+                    rx,
+                    Some(ready_sender),
+                )) {
+                    *last_error.lock().unwrap() = Some(err);
+                }
+            })
+            .map_err(Error::ThreadSpawnFailed)?;
         self.thread = Some(ServiceThreadHandle {
             event_channel,
-            thread: thread::spawn(move || {
-                pollster::block_on(run_service(dbus_name, friendly_name, event_handler, rx))
-                    .unwrap();
-            }),
+            thread,
         });
-        Ok(())
+
+        // The thread drops the sender without a signal if the service fails to start,
+        // returning the error detaching reports.
+        match ready.recv() {
+            Ok(bus_name) => {
+                self.bus_name = Some(bus_name);
+                Ok(())
+            }
+            Err(_) => {
+                self.detach()?;
+                Err(Error::ThreadNotRunning)
+            }
+        }
     }
+
+    /// Run the service on the current thread, sending the media control events to a
+    /// handler, until stopped with a [`StopHandle`] from [`MediaControls::stop_handle`].
+    /// The served state is set meanwhile with a handle from [`MediaControls::run_handle`].
+    ///
+    /// **This blocks the calling thread.** No background thread is spawned, so this
+    /// is meant for programs that have nothing else to do, such as media key daemons.
+    /// In dummy mode, this returns right away unless a stop handle was taken beforehand.
+    pub fn run_blocking<F>(&mut self, event_handler: F) -> Result<(), Error>
+    where
+        F: Fn(MediaControlEvent) + Send + 'static,
+    {
+        self.detach()?;
+        self.setter_state.lock().unwrap().disabled_buttons.clear();
+
+        let stop_channel = self.stop_channel.take();
+        if self.dummy {
+            // Only a stop handle can end the wait, as nothing else is sent in dummy mode.
+            if let Some((_stop_channel, rx)) = stop_channel {
+                rx.recv().ok();
+            }
+            return Ok(());
+        }
+        // Keep a sender alive, so the channel doesn't disconnect without a stop handle.
+        let (_stop_channel, rx) = stop_channel.unwrap_or_else(mpsc::channel);
+
+        pollster::block_on(run_service(
+            self.dbus_name.clone(),
+            self.config.clone(),
+            Arc::new(Mutex::new(event_handler)),
+            rx,
+            None,
+        ))
+    }
+
+    /// Get a handle to stop the service started by the next call to [`MediaControls::attach`]
+    /// or [`MediaControls::run_blocking`]. Calling [`MediaControls::detach`] from the event
+    /// handler would deadlock, since it waits for the thread the handler runs on, so the
+    /// handler can stop the service with this instead.
+    pub fn stop_handle(&mut self) -> StopHandle {
+        let (stop_channel, _) = self.stop_channel.get_or_insert_with(mpsc::channel);
+        StopHandle(stop_channel.clone())
+    }
+
+    /// Get a handle to set the metadata, the playback status, the volume and the buttons
+    /// from other threads, without sharing the controls. Changes made through the handle
+    /// aren't part of a [`MediaControls::update`]. The handle stops working once detached,
+    /// so get a new one after attaching again. Returns [`Error::ThreadNotRunning`] if not
+    /// attached, see [`MediaControls::run_handle`] for [`MediaControls::run_blocking`].
+    /// (Only available on MPRIS)
+    pub fn handle(&self) -> Result<MediaControlsHandle, Error> {
+        let event_channel = match self.thread.as_ref().map(|thread| &thread.event_channel) {
+            Some(event_channel) => Some(event_channel.clone()),
+            // Nothing is ever sent in dummy mode.
+            None if self.dummy => None,
+            None => return Err(Error::ThreadNotRunning),
+        };
+        Ok(MediaControlsHandle {
+            event_channel,
+            setter_state: self.setter_state.clone(),
+            round_duration_to_seconds: self.config.round_duration_to_seconds,
+        })
+    }
+
+    /// Get a handle to set the state served by the next call to
+    /// [`MediaControls::run_blocking`] or [`MediaControls::attach`], like
+    /// [`MediaControls::stop_handle`]. Unlike [`MediaControls::handle`], this works before
+    /// the service runs, so it's how the application changes what `run_blocking` serves,
+    /// from the event handler or another thread, while the controls are borrowed. The
+    /// changes made before the service runs are applied once it does. The handle stops
+    /// working once the service ends. (Only available on MPRIS)
+    pub fn run_handle(&mut self) -> MediaControlsHandle {
+        let dummy = self.dummy;
+        let (event_channel, _) = self.stop_channel.get_or_insert_with(mpsc::channel);
+        MediaControlsHandle {
+            // Nothing is ever sent in dummy mode.
+            event_channel: Some(event_channel.clone()).filter(|_| !dummy),
+            setter_state: self.setter_state.clone(),
+            round_duration_to_seconds: self.config.round_duration_to_seconds,
+        }
+    }
+
     /// Detach the event handler.
+    ///
+    /// The changes sent before detaching, such as a last [`MediaControls::set_metadata`],
+    /// are applied and announced to clients before the bus name is released. Nothing is
+    /// announced afterwards.
     pub fn detach(&mut self) -> Result<(), Error> {
         if let Some(ServiceThreadHandle {
             event_channel,
@@ -116,88 +605,561 @@ impl MediaControls {
         {
             event_channel.send(InternalEvent::Kill).ok();
             thread.join().map_err(|_| Error::ThreadPanicked)?;
+
+            // The thread may have returned an error that wasn't retrieved yet.
+            if let Some(err) = self.last_error() {
+                return Err(err);
+            }
         }
         Ok(())
     }
 
+    /// Whether an event handler is attached. The service may have stopped with an error
+    /// since, see [`MediaControls::last_error`].
+    pub fn is_attached(&self) -> bool {
+        self.thread.is_some()
+    }
+
+    /// The well-known bus name of the service, e.g. `org.mpris.MediaPlayer2.my_player`, with
+    /// the `.instance<pid>` suffix if [`PlatformConfig::dbus_name_pid_suffix`] had to be
+    /// used, or `None` if not attached. (Only available on MPRIS)
+    pub fn well_known_name(&self) -> Option<&str> {
+        self.bus_name.as_deref().filter(|_| self.is_attached())
+    }
+
+    /// Take the error the service thread exited with, if any.
+    ///
+    /// Once taken, the error is cleared, so it's returned only once. This allows
+    /// checking whether the service is still alive without detaching.
+    pub fn last_error(&self) -> Option<Error> {
+        self.last_error.lock().unwrap().take()
+    }
+
     /// Set the current playback status.
     pub fn set_playback(&mut self, playback: MediaPlayback) -> Result<(), Error> {
         self.send_internal_event(InternalEvent::ChangePlayback(playback))?;
         Ok(())
     }
 
+    /// Set the playback status to stopped, and clear the metadata too if `clear_metadata` is
+    /// set. Keep the metadata when the media item stays loaded after stopping, so that
+    /// clients keep showing it, and clear it when there's nothing to resume.
+    ///
+    /// Clearing the metadata leaves only the `mpris:trackid` of [`TrackId::no_track`], sent
+    /// to clients in the same `PropertiesChanged` signal as the status.
+    ///
+    /// The position served to clients is reset to zero, which they read anew along with the
+    /// status, so this is the only call needed to handle [`MediaControlEvent::Stop`].
+    pub fn set_playback_stopped(&mut self, clear_metadata: bool) -> Result<(), Error> {
+        if clear_metadata {
+            return self.set_now_playing(MediaMetadata::default(), MediaPlayback::Stopped);
+        }
+        self.set_playback(MediaPlayback::Stopped)
+    }
+
     /// Set the metadata of the currently playing media item.
+    ///
+    /// A `data:` cover URL too large for [`MediaControls::max_message_size`] is written
+    /// to a temporary file with [`cover_file_url`](crate::cover_file_url) and served
+    /// as a `file://` URL instead.
     pub fn set_metadata(&mut self, metadata: MediaMetadata) -> Result<(), Error> {
-        self.send_internal_event(InternalEvent::ChangeMetadata(metadata.into()))?;
+        let metadata = self.to_owned_metadata(metadata, false);
+        self.send_internal_event(InternalEvent::ChangeMetadata(metadata))?;
+        Ok(())
+    }
+
+    /// Set only the fields of `metadata` that are set, keeping the others, e.g. to add the
+    /// cover art once it's loaded. `live` is only merged when true. Unless set, the track id
+    /// is kept too. Clients are sent the whole merged metadata. (Only available on MPRIS)
+    pub fn merge_metadata(&mut self, metadata: MediaMetadata) -> Result<(), Error> {
+        let metadata = self.to_owned_metadata(metadata, true);
+        self.send_internal_event(InternalEvent::MergeMetadata(metadata))?;
+        Ok(())
+    }
+
+    /// Set only the duration of the current media item, keeping the rest of the metadata,
+    /// including the track id, e.g. once the length of a stream becomes known. `None` removes
+    /// it. Clients are sent the whole metadata. (Only available on MPRIS)
+    pub fn set_duration(&mut self, mut duration: Option<Duration>) -> Result<(), Error> {
+        if self.config.round_duration_to_seconds {
+            duration = duration.map(round_to_seconds);
+        }
+        self.send_internal_event(InternalEvent::ChangeDuration(duration.map(to_micros)))?;
+        Ok(())
+    }
+
+    /// Set the metadata and the playback status of a new media item at once, e.g. when
+    /// changing tracks. Unlike calling [`MediaControls::set_metadata`] and
+    /// [`MediaControls::set_playback`], this guarantees that clients are sent both in a
+    /// single `PropertiesChanged` signal, followed by `Seeked` if the progress requires
+    /// it according to [`PlatformConfig::seeked_emission`], so they never see the new
+    /// metadata with the previous status.
+    pub fn set_now_playing(
+        &mut self,
+        metadata: MediaMetadata,
+        playback: MediaPlayback,
+    ) -> Result<(), Error> {
+        let metadata = self.to_owned_metadata(metadata, false);
+        self.send_internal_event(InternalEvent::ChangeNowPlaying(metadata, playback))?;
+        Ok(())
+    }
+
+    /// Apply the changes made by the setters called in `update` together, so that clients
+    /// are sent them in a single `PropertiesChanged` signal, holding the latest value of
+    /// each property. Changes made before `update` returns an error are still applied.
+    /// (Only available on MPRIS)
+    pub fn update<F>(&mut self, update: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Self) -> Result<(), Error>,
+    {
+        if self.batch.is_some() || self.dummy {
+            // Nested in another update, which sends the events, or nothing to send.
+            return update(self);
+        }
+        if self.thread.is_none() {
+            return Err(Error::ThreadNotRunning);
+        }
+        self.batch = Some(Vec::new());
+        let result = update(self);
+        let events = self.batch.take().unwrap_or_default();
+        if !events.is_empty() {
+            self.send_internal_event(InternalEvent::Batch(events))?;
+        }
+        result
+    }
+
+    /// The maximum size of the D-Bus messages sent by the service, or `None` if not
+    /// attached. The limit of the bus daemon can't be queried over the bus, so this is
+    /// the default limit of `dbus-daemon`. (Only available on MPRIS)
+    pub fn max_message_size(&self) -> Option<usize> {
+        self.thread.as_ref().map(|_| MAX_MESSAGE_SIZE)
+    }
+
+    /// Set a function fetching the bytes of the cover art at a URL, which Windows and
+    /// macOS call to load cover art from local data. On MPRIS, URLs are passed straight
+    /// to clients, so it's never called.
+    pub fn set_cover_fetcher<F>(&mut self, _fetcher: F)
+    where
+        F: Fn(&str) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+    }
+
+    /// Set whether the player is fullscreen, served as the MPRIS `Fullscreen` property.
+    /// (Only available on MPRIS)
+    pub fn set_fullscreen(&mut self, fullscreen: bool) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeFullscreen(fullscreen))?;
+        Ok(())
+    }
+
+    /// Change the display name, served as the MPRIS `Identity` property, without
+    /// re-attaching. (Only available on MPRIS)
+    pub fn set_display_name(&mut self, display_name: &str) -> Result<(), Error> {
+        self.config.friendly_name = display_name.to_owned();
+        self.send_internal_event(InternalEvent::ChangeIdentity(display_name.to_owned()))?;
+        Ok(())
+    }
+
+    /// Report an error, such as a decoding failure or a lost network connection. MPRIS
+    /// has no error status, so playback is stopped and the message is added to the
+    /// metadata under the custom `com.souvlaki:error` key, for clients that want to show
+    /// it. The message is removed by the next call to [`MediaControls::set_metadata`] or
+    /// [`MediaControls::set_playback`].
+    pub fn set_error(&mut self, message: &str) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::Error(message.to_string()))?;
+        Ok(())
+    }
+
+    /// Advertise the discrete playback rates offered by the player, such as
+    /// `[0.5, 1.0, 1.5, 2.0]`, as an array of doubles under the custom
+    /// `com.souvlaki:availableRates` metadata key, for applets that want to show a speed
+    /// menu. The key is omitted when `rates` is empty. MPRIS itself only knows of a
+    /// continuous `Rate`, which is left as it is. (Only available on MPRIS)
+    pub fn set_available_rates(&mut self, rates: &[f64]) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeAvailableRates(rates.to_vec()))?;
+        Ok(())
+    }
+
+    /// Set the user rating (0.0-1.0) of the current media item, served as `xesam:userRating`
+    /// in the metadata, which widgets show as stars. The rating is kept when the metadata is
+    /// set, so set it for every media item, within [`MediaControls::update`] to send both
+    /// together. (Only available on MPRIS)
+    pub fn set_rating(&mut self, rating: f64) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeRating(rating))?;
+        Ok(())
+    }
+
+    /// Show `message` as the title of the current media item for `duration`, after
+    /// which the title is restored. New metadata set in the meantime ends the message
+    /// early. Useful for short notices like "Shuffle on". (Only available on MPRIS)
+    pub fn flash_message(&mut self, message: &str, duration: Duration) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::FlashMessage(message.to_string(), duration))?;
+        Ok(())
+    }
+
+    /// Set the position of the current media item, e.g. after seeking from the application,
+    /// keeping the playback status. The MPRIS `Seeked` signal is always emitted, whatever the
+    /// [`PlatformConfig::seeked_emission`], so that clients update their position. Ignored
+    /// while stopped.
+    pub fn set_position(&mut self, position: MediaPosition) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::SetPosition(position))?;
+        Ok(())
+    }
+
+    /// Set the progress of the current media item, e.g. from a timer while playing, keeping
+    /// the playback status. As with [`MediaControls::set_playback`], the `Seeked` signal is
+    /// emitted according to the [`PlatformConfig::seeked_emission`]; use
+    /// [`MediaControls::set_position`] after seeking instead. Ignored while stopped.
+    pub fn set_progress(&mut self, progress: Duration) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeProgress(MediaPosition(progress)))?;
+        Ok(())
+    }
+
+    /// Report that the current track played until its end. If its duration is known,
+    /// playback is paused at the end of the track and a `Seeked` signal is emitted with
+    /// the duration as position, so that clients show the track as finished instead of
+    /// their position running past it. Otherwise, playback is stopped.
+    ///
+    /// Unlike stopping, this doesn't reset the position, and is meant for the moment
+    /// between two tracks: the next one can be loaded right after with
+    /// [`MediaControls::set_metadata`] and [`MediaControls::set_playback`].
+    pub fn track_ended(&mut self) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::TrackEnded)?;
         Ok(())
     }
 
-    /// Set the volume level (0.0 - 1.0) (Only available on MPRIS)
+    /// The metadata currently served to clients. Setters are applied by the service
+    /// asynchronously, so this may not reflect a change made right before.
+    /// (Only available on MPRIS)
+    pub fn get_metadata(&self) -> Result<OwnedMetadata, Error> {
+        Ok(self.get_state()?.metadata)
+    }
+
+    /// The playback status currently served to clients. Like
+    /// [`MediaControls::get_metadata`], this may not reflect a change made right before.
+    /// (Only available on MPRIS)
+    pub fn get_playback(&self) -> Result<MediaPlayback, Error> {
+        Ok(self.get_state()?.playback_status)
+    }
+
+    /// The volume currently served to clients. Like [`MediaControls::get_metadata`], this
+    /// may not reflect a change made right before. (Only available on MPRIS)
+    pub fn get_volume(&self) -> Result<f64, Error> {
+        Ok(self.get_state()?.volume)
+    }
+
+    /// Set the repeat mode served as the `LoopStatus` property. (Only available on MPRIS)
+    pub fn set_loop_status(&mut self, loop_status: LoopStatus) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeLoopStatus(loop_status))?;
+        Ok(())
+    }
+
+    /// Set whether playback is shuffled, served as the `Shuffle` property.
+    /// (Only available on MPRIS)
+    pub fn set_shuffle(&mut self, shuffle: bool) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeShuffle(shuffle))?;
+        Ok(())
+    }
+
+    /// Set the playback rate served as the `Rate` property, e.g. 1.5 to play 50% faster.
+    /// The position served to clients advances at this rate. (Only available on MPRIS)
+    pub fn set_rate(&mut self, rate: f64) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeRate(rate))?;
+        Ok(())
+    }
+
+    /// Set the volume level, where 1.0 is full volume. Values above 1.0 are allowed for
+    /// amplification, as in MPRIS, and negative values are served as 0.0. Returns
+    /// [`Error::InvalidVolume`] for NaN or an infinite volume. (Only available on MPRIS)
     pub fn set_volume(&mut self, volume: f64) -> Result<(), Error> {
-        self.send_internal_event(InternalEvent::ChangeVolume(volume))?;
+        if !volume.is_finite() {
+            return Err(Error::InvalidVolume(volume));
+        }
+        // Clients are sent the clamped volume, which is also the one read back.
+        self.send_internal_event(InternalEvent::ChangeVolume(volume.max(0.0)))?;
         Ok(())
     }
 
+    /// Set the volume level in decibels relative to full volume, converted to the
+    /// linear volume served to clients as described in [`VolumeScale::Decibels`].
+    /// (Only available on MPRIS)
+    pub fn set_volume_db(&mut self, volume_db: f64) -> Result<(), Error> {
+        self.set_volume(VolumeScale::Decibels.to_linear(volume_db))
+    }
+
+    /// Set the scale of the volume delivered in [`MediaControlEvent::SetVolume`].
+    /// The `Volume` property served to clients stays linear. Takes effect on the next
+    /// [`MediaControls::attach`]. (Only available on MPRIS)
+    pub fn set_volume_scale(&mut self, scale: VolumeScale) {
+        self.config.volume_scale = scale;
+    }
+
     /// Enable or disable a specific media control button.
+    ///
+    /// MPRIS has no `CanStop` property, so `MediaButton::Stop` can't be disabled on its own
+    /// and disabling it has no effect. Use [`MediaControls::set_can_control`] to disable every
+    /// control, Stop included.
     pub fn set_button_enabled(&mut self, button: MediaButton, enabled: bool) -> Result<(), Error> {
         self.send_internal_event(InternalEvent::ChangeButtonEnabled(button, enabled))?;
+        self.record_button_enabled(button, enabled);
+        Ok(())
+    }
+
+    /// Set whether clients can control playback at all, served as the `CanControl` property.
+    /// While it's false, clients are expected to disable every control, including Stop which
+    /// can't be disabled with [`MediaControls::set_button_enabled`], and the methods and
+    /// property changes they call anyway are ignored, e.g. for a view-only "now playing"
+    /// display. Always false in [`PlatformConfig::read_only`] mode. (Only available on MPRIS)
+    pub fn set_can_control(&mut self, can_control: bool) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangeCanControl(can_control))?;
         Ok(())
     }
 
+    /// Whether a specific media control button is enabled. While attached, this is read from
+    /// the state served to clients, so that it agrees with [`MediaControls::capabilities`]:
+    /// no button is enabled while [`MediaControls::set_can_control`] is false, and Seek isn't
+    /// while the media item is live. When not attached, this is as last set with
+    /// [`MediaControls::set_button_enabled`]. There is no `CanStop` property in MPRIS, so
+    /// `MediaButton::Stop` is only disabled along with `CanControl`. No button is enabled in
+    /// [`PlatformConfig::read_only`] mode.
+    pub fn is_button_enabled(&self, button: MediaButton) -> bool {
+        if let Ok(state) = self.get_state() {
+            return is_served_enabled(state.capabilities(), button);
+        }
+        let setter_state = self.setter_state.lock().unwrap();
+        !self.config.read_only
+            && (button == MediaButton::Stop || !setter_state.disabled_buttons.contains(&button))
+    }
+
+    /// The capabilities served to clients, read together so that they're consistent with
+    /// each other. (Only available on MPRIS)
+    pub fn capabilities(&self) -> Result<Capabilities, Error> {
+        Ok(self.get_state()?.capabilities())
+    }
+
+    /// Stop responding to clients temporarily, e.g. during a login prompt, while staying
+    /// registered on the bus and keeping the properties up to date. Until
+    /// [`MediaControls::resume`] is called, the methods called by clients have no effect
+    /// and no event is delivered. The `Can*` properties are left as they are, so that
+    /// clients don't rearrange their controls for a moment: disable buttons with
+    /// [`MediaControls::set_button_enabled`] to grey them out as well.
+    /// Takes effect immediately. (Only available on MPRIS)
+    pub fn suspend(&mut self) {
+        self.config.suspended.store(true, Ordering::Relaxed);
+    }
+
+    /// Respond to clients again after [`MediaControls::suspend`]. (Only available on MPRIS)
+    pub fn resume(&mut self) {
+        self.config.suspended.store(false, Ordering::Relaxed);
+    }
+
+    /// Set the clock used to advance the reported position while playing. Takes effect
+    /// on the next [`MediaControls::attach`]. (Only available on MPRIS)
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.config.clock = clock;
+    }
+
+    /// Set a function transforming the metadata before it is served to clients, e.g. to
+    /// sanitize titles. It is called on the service thread, every time the metadata is
+    /// built, so it should be cheap. Takes effect on the next [`MediaControls::attach`].
+    /// (Only available on MPRIS)
+    pub fn set_metadata_transform<T>(&mut self, transform: T)
+    where
+        T: Fn(OwnedMetadata) -> OwnedMetadata + Send + Sync + 'static,
+    {
+        self.config.metadata_transform = Some(MetadataTransform(Arc::new(transform)));
+    }
+
+    /// Set a function called with the URI a client asks to open, before
+    /// [`MediaControlEvent::OpenUri`] is delivered. The metadata it returns, if any, is
+    /// published right away, so that clients can show the new item while it's loading.
+    ///
+    /// Method calls are handled one at a time, in the order they are received, so the
+    /// event is always delivered before the events of the calls that follow. Takes effect
+    /// on the next [`MediaControls::attach`]. (Only available on MPRIS)
+    pub fn on_open_uri<H>(&mut self, handler: H)
+    where
+        H: Fn(&str) -> Option<OwnedMetadata> + Send + Sync + 'static,
+    {
+        self.config.open_uri_handler = Some(OpenUriHandler(Arc::new(handler)));
+    }
+
+    /// Set the state for when there's nothing to play: playback is stopped and the play,
+    /// pause, next, previous and seek controls are disabled. Enable them again with
+    /// [`MediaControls::set_button_enabled`] once something is loaded.
+    pub fn set_empty_queue(&mut self) -> Result<(), Error> {
+        self.send_internal_event(InternalEvent::ChangePlayback(MediaPlayback::Stopped))?;
+        for button in [
+            MediaButton::Play,
+            MediaButton::Pause,
+            MediaButton::Next,
+            MediaButton::Previous,
+            MediaButton::Seek,
+        ] {
+            self.set_button_enabled(button, false)?;
+        }
+        Ok(())
+    }
+
+    fn record_button_enabled(&mut self, button: MediaButton, enabled: bool) {
+        let mut setter_state = self.setter_state.lock().unwrap();
+        setter_state.record_button_enabled(button, enabled);
+    }
+
+    /// Prepare metadata to be sent to the service, as configured.
+    /// If `merged`, the metadata is merged into the current one, which keeps its track id and
+    /// cover unless they are set.
+    fn to_owned_metadata(&self, metadata: MediaMetadata, merged: bool) -> OwnedMetadata {
+        self.setter_state.lock().unwrap().to_owned_metadata(
+            metadata,
+            merged,
+            self.config.round_duration_to_seconds,
+            self.max_message_size(),
+        )
+    }
+
+    fn get_state(&self) -> Result<ServiceState, Error> {
+        let channel = &self
+            .thread
+            .as_ref()
+            .ok_or(Error::ThreadNotRunning)?
+            .event_channel;
+        let (reply, state) = mpsc::channel();
+        channel
+            .send(InternalEvent::GetState(reply))
+            .map_err(|_| Error::ThreadPanicked)?;
+        state.recv().map_err(|_| Error::ThreadPanicked)
+    }
+
     fn send_internal_event(&mut self, event: InternalEvent) -> Result<(), Error> {
+        if self.dummy {
+            return Ok(());
+        }
+        if let Some(batch) = &mut self.batch {
+            batch.push(event);
+            return Ok(());
+        }
         let channel = &self
             .thread
             .as_ref()
             .ok_or(Error::ThreadNotRunning)?
             .event_channel;
-        channel.send(event).map_err(|_| Error::ThreadPanicked)
+        // The channel is disconnected once the service thread has stopped, as for the handles.
+        channel.send(event).map_err(|_| Error::ThreadNotRunning)
     }
 }
 
 struct AppInterface {
     friendly_name: String,
+    fullscreen: bool,
+    can_raise: bool,
+    can_quit: bool,
+    can_set_fullscreen: bool,
+    supported_uri_schemes: Vec<String>,
+    supported_mime_types: Vec<String>,
     event_handler: Arc<Mutex<dyn Fn(MediaControlEvent) + Send + 'static>>,
 }
 
-#[dbus_interface(name = "org.mpris.MediaPlayer2")]
-impl AppInterface {
-    fn raise(&self) {
-        self.send_event(MediaControlEvent::Raise);
-    }
-    fn quit(&self) {
-        self.send_event(MediaControlEvent::Quit);
-    }
+/// The root interface of a player with a desktop entry. zbus serves every property of an
+/// interface, so `DesktopEntry` is only declared here and the interface is served as an
+/// [`AppInterface`] when the entry isn't set.
+struct DesktopAppInterface {
+    app: AppInterface,
+    desktop_entry: String,
+}
 
-    #[dbus_interface(property)]
-    fn can_quit(&self) -> bool {
-        true
-    }
+impl std::ops::Deref for DesktopAppInterface {
+    type Target = AppInterface;
 
-    #[dbus_interface(property)]
-    fn can_raise(&self) -> bool {
-        true
+    fn deref(&self) -> &AppInterface {
+        &self.app
     }
+}
 
-    #[dbus_interface(property)]
-    fn has_tracklist(&self) -> bool {
-        false
+impl std::ops::DerefMut for DesktopAppInterface {
+    fn deref_mut(&mut self) -> &mut AppInterface {
+        &mut self.app
     }
+}
 
-    #[dbus_interface(property)]
-    fn identity(&self) -> &str {
-        &self.friendly_name
-    }
+/// Implements the root interface for `$interface`, with `$extra` besides the properties
+/// served by every player.
+macro_rules! impl_app_interface {
+    ($interface:ident { $($extra:tt)* }) => {
+        #[dbus_interface(name = "org.mpris.MediaPlayer2")]
+        impl $interface {
+            fn raise(&self) {
+                if self.can_raise {
+                    self.send_event(MediaControlEvent::Raise);
+                }
+            }
+            fn quit(&self) {
+                if self.can_quit {
+                    self.send_event(MediaControlEvent::Quit);
+                }
+            }
 
-    #[dbus_interface(property)]
-    fn supported_uri_schemes(&self) -> &[&str] {
-        &[]
-    }
+            #[dbus_interface(property)]
+            fn can_quit(&self) -> bool {
+                self.can_quit
+            }
+
+            #[dbus_interface(property)]
+            fn can_raise(&self) -> bool {
+                self.can_raise
+            }
+
+            #[dbus_interface(property)]
+            fn fullscreen(&self) -> bool {
+                self.fullscreen
+            }
+
+            #[dbus_interface(property)]
+            fn set_fullscreen(&mut self, fullscreen: bool) {
+                if self.can_set_fullscreen {
+                    // The application confirms the change by calling `set_fullscreen`.
+                    self.send_event(MediaControlEvent::SetFullscreen(fullscreen));
+                }
+            }
+
+            #[dbus_interface(property)]
+            fn can_set_fullscreen(&self) -> bool {
+                self.can_set_fullscreen
+            }
+
+            // The derived name would be `HasTracklist`, which clients don't look up.
+            #[dbus_interface(property, name = "HasTrackList")]
+            fn has_track_list(&self) -> bool {
+                false
+            }
+
+            #[dbus_interface(property)]
+            fn identity(&self) -> &str {
+                &self.friendly_name
+            }
+
+            #[dbus_interface(property)]
+            fn supported_uri_schemes(&self) -> &[String] {
+                &self.supported_uri_schemes
+            }
+
+            #[dbus_interface(property)]
+            fn supported_mime_types(&self) -> &[String] {
+                &self.supported_mime_types
+            }
+
+            $($extra)*
+        }
+    };
+}
+
+impl_app_interface!(AppInterface {});
 
+impl_app_interface!(DesktopAppInterface {
     #[dbus_interface(property)]
-    fn supported_mime_types(&self) -> &[&str] {
-        &[]
+    fn desktop_entry(&self) -> &str {
+        &self.desktop_entry
     }
-}
+});
 
 impl AppInterface {
     fn send_event(&self, event: MediaControlEvent) {
@@ -205,39 +1167,100 @@ impl AppInterface {
     }
 }
 
+struct InfoInterface {
+    display_name: String,
+}
+
+#[dbus_interface(name = "com.souvlaki.Info")]
+impl InfoInterface {
+    #[dbus_interface(property)]
+    fn version(&self) -> &str {
+        env!("CARGO_PKG_VERSION")
+    }
+
+    #[dbus_interface(property)]
+    fn display_name(&self) -> &str {
+        &self.display_name
+    }
+}
+
 struct PlayerInterface {
     state: ServiceState,
+    echo_volume: bool,
+    volume_scale: VolumeScale,
+    minimum_rate: f64,
+    maximum_rate: f64,
+    metadata_transform: Option<MetadataTransform>,
+    open_uri_handler: Option<OpenUriHandler>,
+    suspended: Arc<AtomicBool>,
     event_handler: Arc<Mutex<dyn Fn(MediaControlEvent) + Send + 'static>>,
 }
 
 impl PlayerInterface {
+    /// Send the event of a method call or a property change. According to the MPRIS
+    /// specification, nothing on the player interface has an effect while CanControl is false.
     fn send_event(&self, event: MediaControlEvent) {
-        (self.event_handler.lock().unwrap())(event);
+        if self.state.can_control {
+            (self.event_handler.lock().unwrap())(event);
+        }
+    }
+
+    /// Send the event of a method call, ignoring it while `button` is disabled.
+    fn send_button_event(&self, button: MediaButton, event: MediaControlEvent) {
+        if self.state.is_button_enabled(button) != Some(false) {
+            self.send_event(event);
+        }
+    }
+
+    /// The current value of a property changed by an internal event.
+    fn property_value(&self, name: &str) -> Option<Value<'_>> {
+        let value = match name {
+            "Metadata" => Value::from(self.metadata()),
+            "PlaybackStatus" => Value::from(self.playback_status()),
+            "Volume" => Value::from(self.volume()),
+            "Rate" => Value::from(self.rate()),
+            "LoopStatus" => Value::from(self.loop_status()),
+            "Shuffle" => Value::from(self.shuffle()),
+            "CanPlay" => Value::from(self.can_play()),
+            "CanPause" => Value::from(self.can_pause()),
+            "CanGoNext" => Value::from(self.can_go_next()),
+            "CanGoPrevious" => Value::from(self.can_go_previous()),
+            "CanSeek" => Value::from(self.can_seek()),
+            "CanControl" => Value::from(self.can_control()),
+            _ => return None,
+        };
+        Some(value)
     }
 }
 
 #[dbus_interface(name = "org.mpris.MediaPlayer2.Player")]
 impl PlayerInterface {
     fn next(&self) {
-        self.send_event(MediaControlEvent::Next);
+        self.send_button_event(MediaButton::Next, MediaControlEvent::Next);
     }
     fn previous(&self) {
-        self.send_event(MediaControlEvent::Previous);
+        self.send_button_event(MediaButton::Previous, MediaControlEvent::Previous);
     }
     fn pause(&self) {
-        self.send_event(MediaControlEvent::Pause);
+        self.send_button_event(MediaButton::Pause, MediaControlEvent::Pause);
     }
     fn play_pause(&self) {
-        self.send_event(MediaControlEvent::Toggle);
+        // According to the MPRIS specification, PlayPause depends on CanPause.
+        self.send_button_event(MediaButton::Pause, MediaControlEvent::Toggle);
     }
     fn stop(&self) {
-        self.send_event(MediaControlEvent::Stop);
+        self.send_button_event(MediaButton::Stop, MediaControlEvent::Stop);
     }
     fn play(&self) {
-        self.send_event(MediaControlEvent::Play);
+        self.send_button_event(MediaButton::Play, MediaControlEvent::Play);
     }
 
     fn seek(&self, offset: i64) {
+        // According to the MPRIS specification, the call has no effect if CanSeek is false.
+        if !self.state.is_seekable() {
+            return;
+        }
+
         let abs_offset = offset.unsigned_abs();
         let direction = if offset > 0 {
             SeekDirection::Forward
@@ -253,7 +1276,20 @@ impl PlayerInterface {
         // NOTE: Should the `Seeked` signal be called when calling this method?
     }
 
-    fn set_position(&self, _track_id: zvariant::ObjectPath, position: i64) {
+    fn set_position(&self, track_id: zvariant::ObjectPath, position: i64) {
+        // If CanSeek is false, the call has no effect.
+        if !self.state.is_seekable() {
+            return;
+        }
+
+        // If the TrackId argument is not the same as the current trackid, the call is
+        // ignored as stale. There is no position to set without a track.
+        let current_track_id = self.state.metadata.track_id.as_ref();
+        let is_current = current_track_id.map_or(false, |id| id.as_ref() == track_id.as_str());
+        if !is_current || !self.state.has_track() {
+            return;
+        }
+
         if let Ok(micros) = position.try_into() {
             if let Some(duration) = self.state.metadata.duration {
                 // If the Position argument is greater than the track length, do nothing.
@@ -267,11 +1303,26 @@ impl PlayerInterface {
         }
     }
 
-    fn open_uri(&self, uri: String) {
+    async fn open_uri(&mut self, #[zbus(signal_context)] ctxt: SignalContext<'_>, uri: String) {
         // NOTE: we should check if the URI is in the `SupportedUriSchemes` list.
+        let metadata = self
+            .open_uri_handler
+            .as_ref()
+            .filter(|_| self.state.can_control && !self.suspended.load(Ordering::Relaxed))
+            .and_then(|handler| handler.call(&uri));
+        if let Some(metadata) = metadata {
+            self.state.metadata = metadata;
+            self.state.flash = None;
+            if let Err(err) = self.metadata_changed(&ctxt).await {
+                log::warn!("failed to emit the changed metadata: {}", err);
+            }
+        }
         self.send_event(MediaControlEvent::OpenUri(uri));
     }
 
+    #[dbus_interface(signal)]
+    async fn seeked(ctxt: &SignalContext<'_>, position: i64) -> zbus::Result<()>;
+
     #[dbus_interface(property)]
     fn playback_status(&self) -> &'static str {
         match self.state.playback_status {
@@ -281,48 +1332,144 @@ impl PlayerInterface {
         }
     }
 
+    #[dbus_interface(property)]
+    fn loop_status(&self) -> &'static str {
+        loop_status_name(self.state.loop_status)
+    }
+
+    #[dbus_interface(property)]
+    fn set_loop_status(&mut self, loop_status: &str) -> zbus::fdo::Result<()> {
+        let loop_status = parse_loop_status(loop_status).ok_or_else(|| {
+            zbus::fdo::Error::InvalidArgs(format!("invalid loop status {:?}", loop_status))
+        })?;
+        // The application confirms the change by calling `set_loop_status`.
+        self.send_event(MediaControlEvent::SetLoopStatus(loop_status));
+        Ok(())
+    }
+
+    #[dbus_interface(property)]
+    fn shuffle(&self) -> bool {
+        self.state.shuffle
+    }
+
+    #[dbus_interface(property)]
+    fn set_shuffle(&mut self, shuffle: bool) {
+        // The application confirms the change by calling `set_shuffle`.
+        self.send_event(MediaControlEvent::SetShuffle(shuffle));
+    }
+
     #[dbus_interface(property)]
     fn rate(&self) -> f64 {
-        1.0
+        self.state.rate
     }
 
     #[dbus_interface(property)]
-    fn metadata(&self) -> HashMap<&str, Value> {
+    fn set_rate(&mut self, rate: f64) {
+        let rate = rate.clamp(self.minimum_rate, self.maximum_rate);
+        // The application confirms the change by calling `set_rate`.
+        self.send_event(MediaControlEvent::SetRate(rate));
+    }
+
+    #[dbus_interface(property)]
+    fn metadata(&self) -> HashMap<String, Value> {
         // TODO: this should be stored in a cache inside the state.
-        let mut dict = HashMap::<&str, Value>::new();
+        let mut dict = HashMap::<String, Value>::new();
+        let mut insert = |key: &str, value| dict.insert(key.to_string(), value);
 
+        let mut metadata = match &self.metadata_transform {
+            Some(transform) => transform.apply(self.state.metadata.clone()),
+            None => self.state.metadata.clone(),
+        };
+        if let Some((message, _)) = &self.state.flash {
+            metadata.title = Some(message.clone());
+        }
         let OwnedMetadata {
             ref title,
             ref album,
             ref artist,
+            ref artists,
+            ref album_artists,
+            ref track_number,
+            ref disc_number,
+            ref genre,
+            ref content_created,
             ref cover_url,
             ref duration,
-        } = self.state.metadata;
+            live: _,
+            ref track_id,
+            ref error,
+            ref chapters,
+            ref extra,
+        } = metadata;
 
         // MPRIS
-        dict.insert(
+        let track_id = track_id.clone().unwrap_or_else(TrackId::no_track);
+        insert(
             "mpris:trackid",
-            // TODO: this is just a workaround to enable SetPosition.
-            Value::new(ObjectPath::try_from("/").unwrap()),
+            // Track ids are valid object paths.
+            Value::new(ObjectPath::try_from(track_id.to_string()).unwrap()),
         );
 
         if let Some(length) = duration {
-            dict.insert("mpris:length", Value::new(*length));
+            insert("mpris:length", Value::new(*length));
         }
 
         if let Some(cover_url) = cover_url {
-            dict.insert("mpris:artUrl", Value::new(cover_url.clone()));
+            insert("mpris:artUrl", Value::new(cover_url.clone()));
         }
 
         // Xesam
         if let Some(title) = title {
-            dict.insert("xesam:title", Value::new(title.clone()));
+            insert("xesam:title", Value::new(title.clone()));
         }
-        if let Some(artist) = artist {
-            dict.insert("xesam:artist", Value::new(vec![artist.clone()]));
+        if !artists.is_empty() {
+            insert("xesam:artist", Value::new(artists.clone()));
+        } else if let Some(artist) = artist {
+            insert("xesam:artist", Value::new(vec![artist.clone()]));
         }
         if let Some(album) = album {
-            dict.insert("xesam:album", Value::new(album.clone()));
+            insert("xesam:album", Value::new(album.clone()));
+        }
+        if !album_artists.is_empty() {
+            insert("xesam:albumArtist", Value::new(album_artists.clone()));
+        }
+        if let Some(track_number) = track_number {
+            insert("xesam:trackNumber", Value::new(to_i32(*track_number)));
+        }
+        if let Some(disc_number) = disc_number {
+            insert("xesam:discNumber", Value::new(to_i32(*disc_number)));
+        }
+        if !genre.is_empty() {
+            insert("xesam:genre", Value::new(genre.clone()));
+        }
+        if let Some(content_created) = content_created {
+            insert("xesam:contentCreated", Value::new(content_created.clone()));
+        }
+
+        // Custom
+        if let Some(error) = error {
+            insert(ERROR_METADATA_KEY, Value::new(error.clone()));
+        }
+        if !chapters.is_empty() {
+            insert(CHAPTERS_METADATA_KEY, Value::new(chapters.clone()));
+        }
+        for (key, value) in extra {
+            let value = match value {
+                MetadataValue::String(string) => Value::new(string.clone()),
+                MetadataValue::Integer(integer) => Value::new(*integer),
+                MetadataValue::Float(float) => Value::new(*float),
+                MetadataValue::Strings(strings) => Value::new(strings.clone()),
+            };
+            insert(key, value);
+        }
+        if !self.state.available_rates.is_empty() {
+            insert(
+                AVAILABLE_RATES_METADATA_KEY,
+                Value::new(self.state.available_rates.clone()),
+            );
+        }
+        if let Some(rating) = self.state.rating {
+            insert("xesam:userRating", Value::new(rating));
         }
         dict
     }
@@ -333,33 +1480,31 @@ impl PlayerInterface {
     }
 
     #[dbus_interface(property)]
-    fn set_volume(&self, volume: f64) {
-        self.send_event(MediaControlEvent::SetVolume(volume));
+    fn set_volume(&mut self, volume: f64) {
+        self.send_event(MediaControlEvent::SetVolume(
+            self.volume_scale.convert(volume),
+        ));
+
+        if self.echo_volume && self.state.can_control && !self.suspended.load(Ordering::Relaxed) {
+            // According to the MPRIS specification, a negative volume
+            // should be treated as 0.0.
+            self.state.volume = volume.max(0.0);
+        }
     }
 
     #[dbus_interface(property)]
     fn position(&self) -> i64 {
-        let position = match self.state.playback_status {
-            MediaPlayback::Playing {
-                progress: Some(pos),
-            }
-            | MediaPlayback::Paused {
-                progress: Some(pos),
-            } => pos.0.as_micros(),
-            _ => 0,
-        };
-
-        position.try_into().unwrap_or(0)
+        to_micros(self.state.get_position())
     }
 
     #[dbus_interface(property)]
     fn minimum_rate(&self) -> f64 {
-        1.0
+        self.minimum_rate
     }
 
     #[dbus_interface(property)]
     fn maximum_rate(&self) -> f64 {
-        1.0
+        self.maximum_rate
     }
 
     #[dbus_interface(property)]
@@ -384,55 +1529,201 @@ impl PlayerInterface {
 
     #[dbus_interface(property)]
     fn can_seek(&self) -> bool {
-        self.state.can_seek
+        self.state.is_seekable()
     }
 
     #[dbus_interface(property)]
     fn can_control(&self) -> bool {
-        true
+        self.state.can_control
+    }
+}
+
+/// Convert an error connecting to the bus, telling a missing bus apart.
+fn to_connect_error(err: zbus::Error) -> Error {
+    match err {
+        zbus::Error::Address(message) => Error::NoBus(message),
+        zbus::Error::InputOutput(err)
+            if matches!(
+                err.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            Error::NoBus(err.to_string())
+        }
+        err => err.into(),
+    }
+}
+
+/// Convert an error requesting the bus name, telling a name owned by another connection
+/// apart.
+fn to_request_name_error(err: zbus::Error) -> Error {
+    match err {
+        zbus::Error::NameTaken => Error::NameAlreadyOwned,
+        err => err.into(),
     }
 }
 
 async fn run_service(
     dbus_name: String,
-    friendly_name: String,
+    config: ServiceConfig,
     event_handler: Arc<Mutex<dyn Fn(MediaControlEvent) + Send + 'static>>,
     event_channel: mpsc::Receiver<InternalEvent>,
-) -> zbus::Result<()> {
-    let app = AppInterface {
+    ready: Option<mpsc::Sender<String>>,
+) -> Result<(), Error> {
+    let ServiceConfig {
         friendly_name,
+        dbus_name_pid_suffix,
+        bus,
+        echo_volume,
+        read_only,
+        volume_scale,
+        seeked_emission,
+        seeked_interval,
+        poll_interval,
+        serve_version_info,
+        clamp_position,
+        seeked_on_track_change,
+        initial_volume,
+        minimum_rate,
+        maximum_rate,
+        supported_uri_schemes,
+        supported_mime_types,
+        can_raise,
+        can_quit,
+        can_set_fullscreen,
+        desktop_entry,
+        metadata_transform,
+        open_uri_handler,
+        clock,
+        suspended,
+        ..
+    } = config;
+
+    // Clients can't control a read-only or suspended player.
+    let event_handler: Arc<Mutex<dyn Fn(MediaControlEvent) + Send + 'static>> = if read_only {
+        Arc::new(Mutex::new(|_: MediaControlEvent| ()))
+    } else {
+        let suspended = suspended.clone();
+        Arc::new(Mutex::new(move |event: MediaControlEvent| {
+            if !suspended.load(Ordering::Relaxed) {
+                deliver_event(&*event_handler.lock().unwrap(), event);
+            }
+        }))
+    };
+
+    let info = InfoInterface {
+        display_name: friendly_name.clone(),
+    };
+
+    let app = AppInterface {
+        friendly_name: friendly_name.clone(),
+        fullscreen: false,
+        can_raise,
+        can_quit,
+        can_set_fullscreen,
+        supported_uri_schemes,
+        supported_mime_types,
         event_handler: event_handler.clone(),
     };
 
     let player = PlayerInterface {
         state: ServiceState {
+            identity: friendly_name.clone(),
+            fullscreen: false,
             metadata: OwnedMetadata::default(),
             playback_status: MediaPlayback::Stopped,
-            volume: 1.0,
-            can_play: true,
-            can_pause: true,
-            can_go_next: true,
-            can_go_previous: true,
-            can_seek: true,
+            playback_updated_at: clock.now(),
+            clock: clock.clone(),
+            clamp_position,
+            seeked_on_track_change,
+            volume: initial_volume,
+            read_only,
+            seeked_emission,
+            can_play: !read_only,
+            can_pause: !read_only,
+            can_go_next: !read_only,
+            can_go_previous: !read_only,
+            can_seek: !read_only,
+            can_control: !read_only,
+            rate: 1.0,
+            loop_status: LoopStatus::None,
+            shuffle: false,
+            available_rates: Vec::new(),
+            rating: None,
+            flash: None,
         },
+        echo_volume,
+        volume_scale,
+        minimum_rate,
+        maximum_rate,
+        metadata_transform,
+        // Clients can't change what a read-only player plays.
+        open_uri_handler: open_uri_handler.filter(|_| !read_only),
+        suspended,
         event_handler,
     };
 
-    let name = format!("org.mpris.MediaPlayer2.{dbus_name}");
-    let path = ObjectPath::try_from("/org/mpris/MediaPlayer2")?;
-    let connection = ConnectionBuilder::session()?
-        .serve_at(&path, app)?
-        .serve_at(&path, player)?
-        .name(name.as_str())?
-        .build()
-        .await?;
+    let name = format!("{BUS_NAME_PREFIX}{dbus_name}");
+    let path = ObjectPath::from_static_str_unchecked(MPRIS_OBJECT_PATH);
+    let builder = match bus {
+        BusType::Session => ConnectionBuilder::session(),
+        BusType::System => ConnectionBuilder::system(),
+    };
+    let has_desktop_entry = desktop_entry.is_some();
+    let builder = builder.map_err(to_connect_error)?;
+    let builder = match desktop_entry {
+        Some(desktop_entry) => {
+            builder.serve_at(&path, DesktopAppInterface { app, desktop_entry })?
+        }
+        None => builder.serve_at(&path, app)?,
+    };
+    let mut builder = builder.serve_at(&path, player)?;
+    if serve_version_info {
+        builder = builder.serve_at(&path, info)?;
+    }
+    let connection = builder.build().await.map_err(to_connect_error)?;
+    let name = match connection.request_name(name.as_str()).await {
+        Err(zbus::Error::NameTaken) if dbus_name_pid_suffix => {
+            let name = format!("{name}.instance{}", std::process::id());
+            connection
+                .request_name(name.as_str())
+                .await
+                .map_err(to_request_name_error)?;
+            name
+        }
+        result => {
+            result.map_err(to_request_name_error)?;
+            name
+        }
+    };
+    if let Some(ready) = ready {
+        ready.send(name).ok();
+    }
+
+    // When the flashed message, if any, is to be replaced by the title again.
+    let mut flash_until = None;
+    let mut last_seeked_at = clock.now();
+    // When to emit `Seeked` next, if `seeked_interval` is set and playing.
+    let mut next_seeked_at = None;
 
     loop {
-        if let Ok(event) = event_channel.recv_timeout(Duration::from_millis(10)) {
-            if event == InternalEvent::Kill {
-                break;
-            }
+        // Wake up in time for the next periodic `Seeked` and to end a flashed message.
+        let now = clock.now();
+        let mut timeout = poll_interval;
+        if let Some(at) = next_seeked_at {
+            timeout = timeout.min(at.saturating_duration_since(now));
+        }
+        if let Some(until) = flash_until {
+            timeout = timeout.min(until.saturating_duration_since(now));
+        }
+        let event = event_channel.recv_timeout(timeout).ok();
+        let event = event.or_else(|| {
+            let is_flash_expired = flash_until.map_or(false, |until| clock.now() >= until);
+            is_flash_expired.then_some(InternalEvent::EndFlash)
+        });
+        let is_seeked_due = next_seeked_at.map_or(false, |at| clock.now() >= at);
 
+        if event.is_some() || is_seeked_due {
             let interface_ref = connection
                 .object_server()
                 .interface::<_, PlayerInterface>(&path)
@@ -440,50 +1731,357 @@ async fn run_service(
             let mut interface = interface_ref.get_mut().await;
             let ctxt = SignalContext::new(&connection, &path)?;
 
-            match event {
-                InternalEvent::ChangeMetadata(metadata) => {
-                    interface.state.metadata = metadata;
-                    interface.metadata_changed(&ctxt).await?;
-                }
-                InternalEvent::ChangePlayback(playback) => {
-                    interface.state.playback_status = playback;
-                    interface.playback_status_changed(&ctxt).await?;
-                }
-                InternalEvent::ChangeVolume(volume) => {
-                    interface.state.volume = volume;
-                    interface.volume_changed(&ctxt).await?;
+            // Handle every pending event at once, so that changes made together
+            // are emitted in a single signal.
+            let mut changed_properties = Vec::new();
+            let mut seeked = None;
+            let mut killed = false;
+
+            for event in event.into_iter().chain(event_channel.try_iter()) {
+                if matches!(event, InternalEvent::Kill) {
+                    killed = true;
+                    break;
                 }
-                InternalEvent::ChangeButtonEnabled(button, enabled) => {
-                    match button {
-                        MediaButton::Play => {
-                            interface.state.can_play = enabled;
-                            interface.can_play_changed(&ctxt).await?;
-                        }
-                        MediaButton::Pause => {
-                            interface.state.can_pause = enabled;
-                            interface.can_pause_changed(&ctxt).await?;
-                        }
-                        MediaButton::Next => {
-                            interface.state.can_go_next = enabled;
-                            interface.can_go_next_changed(&ctxt).await?;
-                        }
-                        MediaButton::Previous => {
-                            interface.state.can_go_previous = enabled;
-                            interface.can_go_previous_changed(&ctxt).await?;
-                        }
-                        MediaButton::Seek => {
-                            interface.state.can_seek = enabled;
-                            interface.can_seek_changed(&ctxt).await?;
-                        }
-                        MediaButton::Stop => {
-                            // MPRIS doesn't have a separate CanStop property
-                        }
+                log::trace!("handling {:?}", event);
+                handle_internal_event(
+                    &mut interface.state,
+                    event,
+                    &mut changed_properties,
+                    &mut seeked,
+                );
+            }
+            flash_until = interface.state.flash.as_ref().map(|(_, until)| *until);
+
+            // The playback status may have just changed.
+            let state = &interface.state;
+            let is_seeked_due = state
+                .next_seeked_at(seeked_interval, last_seeked_at)
+                .map_or(false, |at| clock.now() >= at);
+            if is_seeked_due {
+                seeked.get_or_insert_with(|| state.get_position());
+            }
+
+            // The root interface is served apart, so its properties are updated here.
+            let is_identity_changed = changed_properties.contains(&"Identity");
+            let is_fullscreen_changed = changed_properties.contains(&"Fullscreen");
+            // It's served as either type, depending on whether a desktop entry is set.
+            macro_rules! update_app_interface {
+                ($interface:ident) => {{
+                    let app_ref = connection
+                        .object_server()
+                        .interface::<_, $interface>(&path)
+                        .await?;
+                    let mut app = app_ref.get_mut().await;
+                    if is_fullscreen_changed {
+                        app.fullscreen = interface.state.fullscreen;
+                        app.fullscreen_changed(&ctxt).await?;
                     }
+                    if is_identity_changed {
+                        app.friendly_name = interface.state.identity.clone();
+                        app.identity_changed(&ctxt).await?;
+                    }
+                }};
+            }
+            if is_identity_changed || is_fullscreen_changed {
+                if has_desktop_entry {
+                    update_app_interface!(DesktopAppInterface);
+                } else {
+                    update_app_interface!(AppInterface);
                 }
-                InternalEvent::Kill => (),
+            }
+            if is_identity_changed && serve_version_info {
+                let info_ref = connection
+                    .object_server()
+                    .interface::<_, InfoInterface>(&path)
+                    .await?;
+                let mut info = info_ref.get_mut().await;
+                info.display_name = interface.state.identity.clone();
+                info.display_name_changed(&ctxt).await?;
+            }
+
+            changed_properties.sort_unstable();
+            changed_properties.dedup();
+
+            let values: HashMap<&str, Value> = changed_properties
+                .into_iter()
+                .filter_map(|name| Some((name, interface.property_value(name)?)))
+                .collect();
+
+            if !values.is_empty() {
+                log::debug!(
+                    "emitting PropertiesChanged for {:?}",
+                    values.keys().collect::<Vec<_>>()
+                );
+                let values: HashMap<&str, &Value> =
+                    values.iter().map(|(name, value)| (*name, value)).collect();
+                zbus::fdo::Properties::properties_changed(
+                    &ctxt,
+                    InterfaceName::from_static_str_unchecked("org.mpris.MediaPlayer2.Player"),
+                    &values,
+                    &[],
+                )
+                .await?;
+            }
+
+            if let Some(position) = seeked {
+                last_seeked_at = clock.now();
+                let position = to_micros(position);
+                log::debug!("emitting Seeked at {}us", position);
+                PlayerInterface::seeked(&ctxt, position).await?;
+            }
+            next_seeked_at = interface
+                .state
+                .next_seeked_at(seeked_interval, last_seeked_at);
+
+            if killed {
+                break;
             }
         }
     }
 
     Ok(())
 }
+
+fn handle_internal_event(
+    state: &mut ServiceState,
+    event: InternalEvent,
+    changed_properties: &mut Vec<&'static str>,
+    seeked: &mut Option<Duration>,
+) {
+    // Unchanged values aren't announced again, so that setters called on every frame
+    // don't flood the bus.
+    match event {
+        InternalEvent::ChangeMetadata(metadata)
+            if metadata == state.metadata && state.flash.is_none() => {}
+        InternalEvent::ChangeMetadata(metadata) => {
+            let was_seekable = state.is_seekable();
+            let is_new_track = metadata.track_id != state.metadata.track_id;
+            state.metadata = metadata;
+            // New metadata ends any flashed message.
+            state.flash = None;
+            changed_properties.push("Metadata");
+            if state.is_seekable() != was_seekable {
+                changed_properties.push("CanSeek");
+            }
+            if is_new_track && state.seeked_on_track_change {
+                if let Some(playback) =
+                    with_position(&state.playback_status, MediaPosition(Duration::ZERO))
+                {
+                    state.set_playback_status(playback);
+                    *seeked = Some(Duration::ZERO);
+                }
+            }
+        }
+        InternalEvent::MergeMetadata(metadata) => {
+            let mut merged = state.metadata.clone();
+            merged.merge(metadata);
+            handle_internal_event(
+                state,
+                InternalEvent::ChangeMetadata(merged),
+                changed_properties,
+                seeked,
+            );
+        }
+        InternalEvent::ChangeDuration(duration) => {
+            let metadata = OwnedMetadata {
+                // Live media items have no duration.
+                duration: duration.filter(|_| !state.metadata.live),
+                ..state.metadata.clone()
+            };
+            handle_internal_event(
+                state,
+                InternalEvent::ChangeMetadata(metadata),
+                changed_properties,
+                seeked,
+            );
+        }
+        InternalEvent::ChangePlayback(playback) => {
+            if state.metadata.error.take().is_some() {
+                changed_properties.push("Metadata");
+            }
+            if let MediaPlayback::Playing {
+                progress: Some(progress),
+            }
+            | MediaPlayback::Paused {
+                progress: Some(progress),
+            } = playback
+            {
+                if is_seek(state.seeked_emission, state.get_position(), progress.0) {
+                    *seeked = Some(progress.0);
+                }
+            }
+            let previous_status = std::mem::discriminant(&state.playback_status);
+            // The progress is still updated, as it moves on even with the same status.
+            state.set_playback_status(playback);
+            if std::mem::discriminant(&state.playback_status) != previous_status {
+                changed_properties.push("PlaybackStatus");
+            }
+        }
+        InternalEvent::SetPosition(position) => {
+            // Only the position changes, which clients learn from `Seeked`.
+            if let Some(playback) = with_position(&state.playback_status, position) {
+                state.set_playback_status(playback);
+                *seeked = Some(position.0);
+            }
+        }
+        InternalEvent::ChangeProgress(position) => {
+            if let Some(playback) = with_position(&state.playback_status, position) {
+                handle_internal_event(
+                    state,
+                    InternalEvent::ChangePlayback(playback),
+                    changed_properties,
+                    seeked,
+                );
+            }
+        }
+        InternalEvent::ChangeVolume(volume) if volume == state.volume => {}
+        InternalEvent::ChangeVolume(volume) => {
+            state.volume = volume;
+            changed_properties.push("Volume");
+        }
+        InternalEvent::ChangeRate(rate) if rate == state.rate => {}
+        InternalEvent::ChangeRate(rate) => {
+            state.set_rate(rate);
+            changed_properties.push("Rate");
+        }
+        InternalEvent::ChangeLoopStatus(loop_status) if loop_status == state.loop_status => {}
+        InternalEvent::ChangeLoopStatus(loop_status) => {
+            state.loop_status = loop_status;
+            changed_properties.push("LoopStatus");
+        }
+        InternalEvent::ChangeShuffle(shuffle) if shuffle == state.shuffle => {}
+        InternalEvent::ChangeShuffle(shuffle) => {
+            state.shuffle = shuffle;
+            changed_properties.push("Shuffle");
+        }
+        InternalEvent::ChangeAvailableRates(rates) if rates == state.available_rates => {}
+        InternalEvent::ChangeAvailableRates(rates) => {
+            state.available_rates = rates;
+            changed_properties.push("Metadata");
+        }
+        InternalEvent::ChangeRating(rating) if state.rating == Some(rating) => {}
+        InternalEvent::ChangeRating(rating) => {
+            state.rating = Some(rating);
+            changed_properties.push("Metadata");
+        }
+        InternalEvent::ChangeButtonEnabled(..) if state.read_only => {
+            // Every button stays disabled.
+        }
+        InternalEvent::ChangeButtonEnabled(button, enabled)
+            if state.is_button_enabled(button) == Some(enabled) => {}
+        InternalEvent::ChangeButtonEnabled(button, enabled) => match button {
+            MediaButton::Play => {
+                state.can_play = enabled;
+                changed_properties.push("CanPlay");
+            }
+            MediaButton::Pause => {
+                state.can_pause = enabled;
+                changed_properties.push("CanPause");
+            }
+            MediaButton::Next => {
+                state.can_go_next = enabled;
+                changed_properties.push("CanGoNext");
+            }
+            MediaButton::Previous => {
+                state.can_go_previous = enabled;
+                changed_properties.push("CanGoPrevious");
+            }
+            MediaButton::Seek => {
+                state.can_seek = enabled;
+                changed_properties.push("CanSeek");
+            }
+            MediaButton::Stop => {
+                // MPRIS doesn't have a separate CanStop property, only CanControl.
+            }
+        },
+        InternalEvent::ChangeCanControl(..) if state.read_only => {
+            // Control stays disabled.
+        }
+        InternalEvent::ChangeCanControl(can_control) if can_control == state.can_control => {}
+        InternalEvent::ChangeCanControl(can_control) => {
+            state.can_control = can_control;
+            changed_properties.push("CanControl");
+        }
+        InternalEvent::ChangeIdentity(identity) if identity == state.identity => {}
+        InternalEvent::ChangeIdentity(identity) => {
+            state.identity = identity;
+            // Served by the root interface, which is updated by the service.
+            changed_properties.push("Identity");
+        }
+        InternalEvent::ChangeFullscreen(fullscreen) if fullscreen == state.fullscreen => {}
+        InternalEvent::ChangeFullscreen(fullscreen) => {
+            state.fullscreen = fullscreen;
+            // Served by the root interface too.
+            changed_properties.push("Fullscreen");
+        }
+        InternalEvent::Error(message) => {
+            state.metadata.error = Some(message);
+            state.set_playback_status(MediaPlayback::Stopped);
+            changed_properties.push("Metadata");
+            changed_properties.push("PlaybackStatus");
+        }
+        InternalEvent::ChangeNowPlaying(metadata, playback) => {
+            handle_internal_event(
+                state,
+                InternalEvent::ChangeMetadata(metadata),
+                changed_properties,
+                seeked,
+            );
+            // A new media item is expected to start at the beginning, not where the
+            // previous one was.
+            if let Some(playback) =
+                with_position(&state.playback_status, MediaPosition(Duration::ZERO))
+            {
+                state.set_playback_status(playback);
+            }
+            handle_internal_event(
+                state,
+                InternalEvent::ChangePlayback(playback),
+                changed_properties,
+                seeked,
+            );
+            // Announce where the new media item actually starts.
+            if let Some(position) = seeked {
+                *position = state.get_position();
+            }
+        }
+        InternalEvent::TrackEnded => {
+            // Rest at the end of the track, so that the position doesn't run past it.
+            let playback = match state.metadata.duration.map(u64::try_from) {
+                Some(Ok(duration)) => {
+                    let position = Duration::from_micros(duration);
+                    *seeked = Some(position);
+                    MediaPlayback::Paused {
+                        progress: Some(MediaPosition(position)),
+                    }
+                }
+                _ => MediaPlayback::Stopped,
+            };
+            handle_internal_event(
+                state,
+                InternalEvent::ChangePlayback(playback),
+                changed_properties,
+                seeked,
+            );
+        }
+        InternalEvent::FlashMessage(message, duration) => {
+            state.flash = Some((message, state.clock.now() + duration));
+            changed_properties.push("Metadata");
+        }
+        InternalEvent::EndFlash => {
+            // The flash may have already been ended by new metadata.
+            if state.flash.take().is_some() {
+                changed_properties.push("Metadata");
+            }
+        }
+        InternalEvent::GetState(reply) => {
+            reply.send(state.clone()).ok();
+        }
+        InternalEvent::Batch(events) => {
+            for event in events {
+                handle_internal_event(state, event, changed_properties, seeked);
+            }
+        }
+        InternalEvent::Kill => (),
+    }
+}
@@ -1,14 +1,19 @@
 #![cfg(target_os = "windows")]
 
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::Duration;
 use windows::core::{Error as WindowsError, HSTRING};
 use windows::Foundation::{EventRegistrationToken, TimeSpan, TypedEventHandler, Uri};
 use windows::Media::*;
-use windows::Storage::Streams::RandomAccessStreamReference;
+use windows::Storage::Streams::{
+    DataWriter, InMemoryRandomAccessStream, RandomAccessStreamReference,
+};
 use windows::Win32::Foundation::HWND;
 use windows::Win32::System::WinRT::ISystemMediaTransportControlsInterop;
 use windows::Win32::UI::Shell::SetCurrentProcessExplicitAppUserModelID;
+use windows::Win32::UI::WindowsAndMessaging::IsWindow;
 
 use crate::{
     MediaButton, MediaControlEvent, MediaMetadata, MediaPlayback, MediaPosition, PlatformConfig,
@@ -19,8 +24,12 @@ use crate::{
 pub struct MediaControls {
     controls: SystemMediaTransportControls,
     button_handler_token: Option<EventRegistrationToken>,
+    position_handler_token: Option<EventRegistrationToken>,
     display_updater: SystemMediaTransportControlsDisplayUpdater,
     timeline_properties: SystemMediaTransportControlsTimelineProperties,
+    cover_fetcher: Option<Arc<dyn Fn(&str) -> Option<Vec<u8>> + Send + Sync>>,
+    /// Counts the metadata updates, so that a cover fetched for a previous one is dropped.
+    metadata_counter: Arc<AtomicUsize>,
 }
 
 #[repr(i32)]
@@ -33,19 +42,34 @@ enum SmtcPlayback {
 
 /// A platform-specific error.
 #[derive(Debug)]
-pub struct Error(WindowsError);
+pub enum Error {
+    /// No HWND was set in the [`PlatformConfig`], or it isn't the handle of an existing
+    /// window, e.g. because the window was already destroyed.
+    InvalidWindowHandle,
+    Windows(WindowsError),
+}
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        self.0.fmt(f)
+        match self {
+            Error::InvalidWindowHandle => f.write_str("the HWND isn't a valid window handle"),
+            Error::Windows(err) => err.fmt(f),
+        }
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidWindowHandle => None,
+            Error::Windows(err) => Some(err),
+        }
+    }
+}
 
 impl From<WindowsError> for Error {
     fn from(other: WindowsError) -> Error {
-        Error(other)
+        Error::Windows(other)
     }
 }
 
@@ -65,12 +89,16 @@ impl MediaControls {
             SystemMediaTransportControls,
             ISystemMediaTransportControlsInterop,
         >()?;
-        let hwnd = config
-            .hwnd
-            .expect("Windows media controls require an HWND in MediaControlsOptions.");
+        // SMTC fails with an obscure error on a handle that isn't a window.
+        let hwnd = match config.hwnd {
+            Some(hwnd) if !hwnd.is_null() => HWND(hwnd as isize),
+            _ => return Err(Error::InvalidWindowHandle),
+        };
+        if !unsafe { IsWindow(hwnd) }.as_bool() {
+            return Err(Error::InvalidWindowHandle);
+        }
 
-        let controls: SystemMediaTransportControls =
-            unsafe { interop.GetForWindow(HWND(hwnd as isize)) }?;
+        let controls: SystemMediaTransportControls = unsafe { interop.GetForWindow(hwnd) }?;
         let display_updater = controls.DisplayUpdater()?;
         let timeline_properties = SystemMediaTransportControlsTimelineProperties::new()?;
 
@@ -79,14 +107,24 @@ impl MediaControls {
             display_updater,
             timeline_properties,
             button_handler_token: None,
+            position_handler_token: None,
+            #[cfg(feature = "http_cover")]
+            cover_fetcher: Some(Arc::new(crate::fetch_http_cover)),
+            #[cfg(not(feature = "http_cover"))]
+            cover_fetcher: None,
+            metadata_counter: Arc::new(AtomicUsize::new(0)),
         })
     }
 
     /// Attach the media control events to a handler.
+    ///
+    /// Attaching again replaces the previous handler.
     pub fn attach<F>(&mut self, event_handler: F) -> Result<(), Error>
     where
         F: Fn(MediaControlEvent) + Send + 'static,
     {
+        self.detach()?;
+
         self.controls.SetIsEnabled(true)?;
         self.controls.SetIsPlayEnabled(true)?;
         self.controls.SetIsPauseEnabled(true)?;
@@ -145,8 +183,10 @@ impl MediaControls {
                 Ok(())
             }
         });
-        self.controls
-            .PlaybackPositionChangeRequested(&position_handler)?;
+        self.position_handler_token = Some(
+            self.controls
+                .PlaybackPositionChangeRequested(&position_handler)?,
+        );
 
         Ok(())
     }
@@ -154,12 +194,21 @@ impl MediaControls {
     /// Detach the event handler.
     pub fn detach(&mut self) -> Result<(), Error> {
         self.controls.SetIsEnabled(false)?;
-        if let Some(button_handler_token) = self.button_handler_token {
+        if let Some(button_handler_token) = self.button_handler_token.take() {
             self.controls.RemoveButtonPressed(button_handler_token)?;
         }
+        if let Some(position_handler_token) = self.position_handler_token.take() {
+            self.controls
+                .RemovePlaybackPositionChangeRequested(position_handler_token)?;
+        }
         Ok(())
     }
 
+    /// Whether an event handler is attached.
+    pub fn is_attached(&self) -> bool {
+        self.button_handler_token.is_some()
+    }
+
     /// Set the current playback status.
     pub fn set_playback(&mut self, playback: MediaPlayback) -> Result<(), Error> {
         let status = match playback {
@@ -186,6 +235,18 @@ impl MediaControls {
         Ok(())
     }
 
+    /// Set the playback status to stopped, and clear the metadata too if `clear_metadata` is
+    /// set. Keep the metadata when the media item stays loaded after stopping, so that
+    /// clients keep showing it, and clear it when there's nothing to resume.
+    pub fn set_playback_stopped(&mut self, clear_metadata: bool) -> Result<(), Error> {
+        if clear_metadata {
+            self.display_updater.ClearAll()?;
+            self.display_updater.SetType(MediaPlaybackType::Music)?;
+            self.display_updater.Update()?;
+        }
+        self.set_playback(MediaPlayback::Stopped)
+    }
+
     /// Set the metadata of the currently playing media item.
     pub fn set_metadata(&mut self, metadata: MediaMetadata) -> Result<(), Error> {
         let properties = self.display_updater.MusicProperties()?;
@@ -193,28 +254,62 @@ impl MediaControls {
         if let Some(title) = metadata.title {
             properties.SetTitle(&HSTRING::from(title))?;
         }
-        if let Some(artist) = metadata.artist {
+        if let Some(artist) = metadata.joined_artists() {
             properties.SetArtist(&HSTRING::from(artist))?;
         }
         if let Some(album) = metadata.album {
             properties.SetAlbumTitle(&HSTRING::from(album))?;
         }
-        if let Some(url) = metadata.cover_url {
-            let stream = if url.starts_with("file://") {
-                // url is a file, load it manually
-                let path = url.trim_start_matches("file://");
-                let loader =
-                    windows::Storage::StorageFile::GetFileFromPathAsync(&HSTRING::from(path))?;
-                let results = loader.get()?;
-                loader.Close()?;
-
-                RandomAccessStreamReference::CreateFromFile(&results)?
-            } else {
-                RandomAccessStreamReference::CreateFromUri(&Uri::CreateUri(&HSTRING::from(url))?)?
-            };
-            self.display_updater.SetThumbnail(&stream)?;
+        if let Some(album_artists) = &metadata.album_artists {
+            properties.SetAlbumArtist(&HSTRING::from(album_artists.join(", ")))?;
+        }
+        if let Some(track_number) = metadata.track_number {
+            properties.SetTrackNumber(track_number)?;
+        }
+        if let Some(genre) = &metadata.genre {
+            let genres = properties.Genres()?;
+            genres.Clear()?;
+            for genre in genre {
+                genres.Append(&HSTRING::from(*genre))?;
+            }
+        }
+        let counter = self.metadata_counter.fetch_add(1, Ordering::SeqCst) + 1;
+        match (metadata.cover_url, &self.cover_fetcher) {
+            (Some(url), Some(fetcher)) if !url.starts_with("file://") => {
+                let url = url.to_owned();
+                let fetcher = fetcher.clone();
+                let metadata_counter = self.metadata_counter.clone();
+                let display_updater = self.display_updater.clone();
+
+                thread::spawn(move || {
+                    let url = crate::fetch_cover_file_url(&*fetcher, &url).unwrap_or(url);
+                    if metadata_counter.load(Ordering::SeqCst) != counter {
+                        return;
+                    }
+                    let set_thumbnail = || -> Result<(), Error> {
+                        display_updater.SetThumbnail(&thumbnail_stream(&url)?)?;
+                        display_updater.Update()?;
+                        Ok(())
+                    };
+                    // There's nobody to report an error to on this thread.
+                    set_thumbnail().ok();
+                });
+            }
+            (Some(url), _) => {
+                self.display_updater.SetThumbnail(&thumbnail_stream(url)?)?;
+            }
+            (None, _) => {
+                if let Some((_, bytes)) = metadata.cover_bytes {
+                    self.display_updater
+                        .SetThumbnail(&thumbnail_stream_from_bytes(bytes)?)?;
+                }
+            }
         }
-        let duration = metadata.duration.unwrap_or_default();
+        // Live streams have no timeline.
+        let duration = match metadata.duration {
+            Some(duration) if !metadata.live => duration,
+            _ => Duration::ZERO,
+        };
         self.timeline_properties.SetStartTime(TimeSpan::default())?;
         self.timeline_properties
             .SetMinSeekTime(TimeSpan::default())?;
@@ -229,6 +324,32 @@ impl MediaControls {
         Ok(())
     }
 
+    /// Set the metadata and the playback status of a new media item at once, e.g. when
+    /// changing tracks.
+    pub fn set_now_playing(
+        &mut self,
+        metadata: MediaMetadata,
+        playback: MediaPlayback,
+    ) -> Result<(), Error> {
+        self.set_metadata(metadata)?;
+        self.set_playback(playback)
+    }
+
+    /// Set a function fetching the bytes of the cover art at a URL, e.g. over HTTP, for
+    /// apps that only have a URL of the art. It's called on a separate thread for each
+    /// cover URL not starting with `file://` passed to [`MediaControls::set_metadata`],
+    /// and the bytes are written to a temporary file with
+    /// [`cover_file_url`](crate::cover_file_url) to be loaded from there. If it returns
+    /// `None`, the URL is loaded as it is. On MPRIS, URLs are passed straight to clients
+    /// and the fetcher is never called. With the `http_cover` feature,
+    /// [`fetch_http_cover`](crate::fetch_http_cover) is used until another one is set.
+    pub fn set_cover_fetcher<F>(&mut self, fetcher: F)
+    where
+        F: Fn(&str) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.cover_fetcher = Some(Arc::new(fetcher));
+    }
+
     /// Enable or disable a specific media control button.
     pub fn set_button_enabled(&mut self, button: MediaButton, enabled: bool) -> Result<(), Error> {
         match button {
@@ -244,4 +365,121 @@ impl MediaControls {
         }
         Ok(())
     }
+
+    /// Whether a specific media control button is enabled.
+    pub fn is_button_enabled(&self, button: MediaButton) -> bool {
+        let enabled = match button {
+            MediaButton::Play => self.controls.IsPlayEnabled(),
+            MediaButton::Pause => self.controls.IsPauseEnabled(),
+            MediaButton::Stop => self.controls.IsStopEnabled(),
+            MediaButton::Next => self.controls.IsNextEnabled(),
+            MediaButton::Previous => self.controls.IsPreviousEnabled(),
+            MediaButton::Seek => self
+                .controls
+                .IsFastForwardEnabled()
+                .and_then(|fast_forward| {
+                    let rewind = self.controls.IsRewindEnabled()?;
+                    Ok(fast_forward && rewind)
+                }),
+        };
+        enabled.unwrap_or(false)
+    }
+
+    /// Report an error, such as a decoding failure or a lost network connection. SMTC
+    /// has no error status, so playback is stopped and the message is ignored.
+    pub fn set_error(&mut self, _message: &str) -> Result<(), Error> {
+        self.set_playback(MediaPlayback::Stopped)
+    }
+
+    /// Set the position of the current media item, e.g. after seeking from the application,
+    /// keeping the playback status.
+    pub fn set_position(&mut self, position: MediaPosition) -> Result<(), Error> {
+        self.timeline_properties
+            .SetPosition(TimeSpan::from(position.0))?;
+        self.controls
+            .UpdateTimelineProperties(&self.timeline_properties)?;
+        Ok(())
+    }
+
+    /// Set the progress of the current media item, e.g. from a timer while playing, keeping
+    /// the playback status. The same as [`MediaControls::set_position`] on this platform.
+    pub fn set_progress(&mut self, progress: Duration) -> Result<(), Error> {
+        self.set_position(MediaPosition(progress))
+    }
+
+    /// Report that the current track played until its end. If its duration is known,
+    /// playback is paused at the end of the track, otherwise it's stopped.
+    pub fn track_ended(&mut self) -> Result<(), Error> {
+        let duration = Duration::from(self.timeline_properties.EndTime()?);
+        let playback = if duration.is_zero() {
+            MediaPlayback::Stopped
+        } else {
+            MediaPlayback::Paused {
+                progress: Some(MediaPosition(duration)),
+            }
+        };
+        self.set_playback(playback)
+    }
+
+    /// Set the state for when there's nothing to play: playback is stopped and the play,
+    /// pause, next, previous and seek controls are disabled. Enable them again with
+    /// [`MediaControls::set_button_enabled`] once something is loaded.
+    pub fn set_empty_queue(&mut self) -> Result<(), Error> {
+        for button in [
+            MediaButton::Play,
+            MediaButton::Pause,
+            MediaButton::Next,
+            MediaButton::Previous,
+            MediaButton::Seek,
+        ] {
+            self.set_button_enabled(button, false)?;
+        }
+        self.set_playback(MediaPlayback::Stopped)
+    }
+}
+
+/// Load a cover art URL as a thumbnail.
+fn thumbnail_stream(url: &str) -> Result<RandomAccessStreamReference, Error> {
+    let stream = if url.starts_with("file://") {
+        // url is a file, load it manually
+        let path = url.trim_start_matches("file://");
+        let loader = windows::Storage::StorageFile::GetFileFromPathAsync(&HSTRING::from(path))?;
+        let results = loader.get()?;
+        loader.Close()?;
+
+        RandomAccessStreamReference::CreateFromFile(&results)?
+    } else {
+        RandomAccessStreamReference::CreateFromUri(&Uri::CreateUri(&HSTRING::from(url))?)?
+    };
+    Ok(stream)
+}
+
+fn thumbnail_stream_from_bytes(bytes: &[u8]) -> Result<RandomAccessStreamReference, Error> {
+    let stream = InMemoryRandomAccessStream::new()?;
+    let writer = DataWriter::CreateDataWriter(&stream)?;
+    writer.WriteBytes(bytes)?;
+    writer.StoreAsync()?.get()?;
+    // Keep the stream open when the writer is dropped.
+    writer.DetachStream()?;
+    stream.Seek(0)?;
+    Ok(RandomAccessStreamReference::CreateFromStream(&stream)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn null_window_handle_is_rejected() {
+        for hwnd in [None, Some(std::ptr::null_mut())] {
+            let config = PlatformConfig {
+                hwnd,
+                ..Default::default()
+            };
+            assert!(matches!(
+                MediaControls::new(config),
+                Err(Error::InvalidWindowHandle)
+            ));
+        }
+    }
 }
@@ -0,0 +1,94 @@
+use std::borrow::Cow;
+use std::fmt;
+
+/// The identifier of a media item, served as `mpris:trackid` on MPRIS.
+///
+/// It must be a valid
+/// [D-Bus object path](https://dbus.freedesktop.org/doc/dbus-specification.html#message-protocol-marshaling-object-path),
+/// e.g. `/com/example/player/track/42`, which is checked on construction so that a
+/// malformed path never reaches D-Bus.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct TrackId(Cow<'static, str>);
+
+impl TrackId {
+    /// Create a track id, checking that it's a valid D-Bus object path.
+    pub fn new(path: &str) -> Result<Self, InvalidTrackId> {
+        if is_object_path(path) {
+            Ok(Self(Cow::Owned(path.to_owned())))
+        } else {
+            Err(InvalidTrackId(path.to_owned()))
+        }
+    }
+
+    /// The id MPRIS defines for no media item, `/org/mpris/MediaPlayer2/TrackList/NoTrack`.
+    /// Used for empty metadata, for which there is no position to get or set.
+    pub const fn no_track() -> Self {
+        Self(Cow::Borrowed("/org/mpris/MediaPlayer2/TrackList/NoTrack"))
+    }
+
+    /// Whether this is [`TrackId::no_track`].
+    pub fn is_no_track(&self) -> bool {
+        *self == Self::no_track()
+    }
+}
+
+impl Default for TrackId {
+    fn default() -> Self {
+        Self::no_track()
+    }
+}
+
+impl AsRef<str> for TrackId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for TrackId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for TrackId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+/// Checked like [`TrackId::new`].
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for TrackId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let path = String::deserialize(deserializer)?;
+        Self::new(&path).map_err(serde::de::Error::custom)
+    }
+}
+
+/// The error returned by [`TrackId::new`] for a string that isn't a D-Bus object path.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InvalidTrackId(pub String);
+
+impl fmt::Display for InvalidTrackId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid track id {:?}: not a D-Bus object path", self.0)
+    }
+}
+
+impl std::error::Error for InvalidTrackId {}
+
+/// Whether `path` is `/`, or `/` followed by non-empty elements of ASCII letters, digits
+/// and underscores separated by `/`.
+fn is_object_path(path: &str) -> bool {
+    match path.strip_prefix('/') {
+        Some("") => true,
+        Some(elements) => elements.split('/').all(|element| {
+            !element.is_empty()
+                && element
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'_')
+        }),
+        None => false,
+    }
+}
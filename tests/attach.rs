@@ -0,0 +1,64 @@
+#![cfg(all(target_os = "linux", feature = "use_dbus"))]
+
+mod common;
+
+use std::sync::mpsc;
+
+use dbus::blocking::stdintf::org_freedesktop_dbus::RequestNameReply;
+use dbus::blocking::Connection;
+use souvlaki::{MediaControlEvent, MediaControls, PlatformConfig};
+
+use common::{event_sender, TemporaryBus, BUS_NAME, TIMEOUT};
+
+fn play_pause(client: &Connection) {
+    client
+        .with_proxy(BUS_NAME, "/org/mpris/MediaPlayer2", TIMEOUT)
+        .method_call::<(), _, _, _>("org.mpris.MediaPlayer2.Player", "PlayPause", ())
+        .expect("PlayPause failed");
+}
+
+#[test]
+fn attaching_again_replaces_the_handler() {
+    let bus = match TemporaryBus::start() {
+        Some(bus) => bus,
+        None => return,
+    };
+    let client = bus.connect();
+    let mut controls = MediaControls::new(PlatformConfig::default()).unwrap();
+    assert!(!controls.is_attached());
+
+    let (first_handler, first_events) = event_sender();
+    controls
+        .attach_with_connection(bus.connect(), first_handler)
+        .unwrap();
+    assert!(controls.is_attached());
+    play_pause(&client);
+    assert_eq!(
+        first_events.recv_timeout(TIMEOUT),
+        Ok(MediaControlEvent::Toggle)
+    );
+
+    let (second_handler, second_events) = event_sender();
+    controls
+        .attach_with_connection(bus.connect(), second_handler)
+        .unwrap();
+    assert!(controls.is_attached());
+    play_pause(&client);
+    assert_eq!(
+        second_events.recv_timeout(TIMEOUT),
+        Ok(MediaControlEvent::Toggle)
+    );
+    // The first handler was dropped with its service thread.
+    assert_eq!(
+        first_events.try_recv(),
+        Err(mpsc::TryRecvError::Disconnected)
+    );
+
+    // The name is owned by the new service, so another player can't take it.
+    let other = bus.connect();
+    let reply = other.request_name(BUS_NAME, false, false, true).unwrap();
+    assert_eq!(reply, RequestNameReply::Exists);
+
+    controls.detach().unwrap();
+    assert!(!controls.is_attached());
+}
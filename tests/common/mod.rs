@@ -0,0 +1,75 @@
+//! Helpers for the tests run against a D-Bus daemon.
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use dbus::blocking::Connection;
+use dbus::channel::Channel;
+use souvlaki::MediaControlEvent;
+
+/// The bus name of the player with the default [`souvlaki::PlatformConfig`].
+pub const BUS_NAME: &str = "org.mpris.MediaPlayer2.souvlaki_player";
+
+/// How long to wait for a reply, an event or a signal before failing.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An event handler sending the events to the returned receiver.
+pub fn event_sender() -> (
+    impl Fn(MediaControlEvent) + Send + 'static,
+    mpsc::Receiver<MediaControlEvent>,
+) {
+    let (tx, rx) = mpsc::channel();
+    let handler = move |event: MediaControlEvent| tx.send(event).unwrap_or(());
+    (handler, rx)
+}
+
+/// A private session bus, run by `dbus-daemon` for the duration of a test, so that the tests
+/// neither need nor disturb the bus of the user's session.
+pub struct TemporaryBus {
+    daemon: Child,
+    address: String,
+}
+
+impl TemporaryBus {
+    /// Start a bus, or `None` if `dbus-daemon` isn't available, in which case the test should
+    /// be skipped.
+    pub fn start() -> Option<Self> {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| eprintln!("skipped: can't run dbus-daemon: {}", err))
+            .ok()?;
+
+        // The address is printed once the daemon listens.
+        let mut address = String::new();
+        let stdout = daemon.stdout.take().expect("stdout is piped");
+        BufReader::new(stdout).read_line(&mut address).ok();
+        let bus = Self {
+            daemon,
+            address: address.trim_end().to_owned(),
+        };
+        if bus.address.is_empty() {
+            eprintln!("skipped: dbus-daemon printed no address");
+            return None;
+        }
+        Some(bus)
+    }
+
+    /// Open a new connection to the bus.
+    pub fn connect(&self) -> Connection {
+        let mut channel = Channel::open_private(&self.address).expect("failed to open the bus");
+        channel.register().expect("failed to register on the bus");
+        Connection::from(channel)
+    }
+}
+
+impl Drop for TemporaryBus {
+    fn drop(&mut self) {
+        self.daemon.kill().ok();
+        self.daemon.wait().ok();
+    }
+}
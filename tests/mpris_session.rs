@@ -0,0 +1,104 @@
+#![cfg(all(target_os = "linux", feature = "use_dbus"))]
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use dbus::arg::{prop_cast, PropMap, RefArg};
+use dbus::blocking::stdintf::org_freedesktop_dbus::{Properties, PropertiesPropertiesChanged};
+use dbus::blocking::Connection;
+use dbus::Message;
+use souvlaki::{MediaControlEvent, MediaControls, MediaMetadata, PlatformConfig};
+
+use common::{event_sender, TemporaryBus, BUS_NAME, TIMEOUT};
+
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+const TITLE: &str = "Souvlaki Space Station";
+
+/// The title in a `Metadata` dict as parsed from a signal.
+fn title(metadata: &dyn RefArg) -> Option<String> {
+    let mut entries = metadata.as_iter()?;
+    while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+        if key.as_str() == Some("xesam:title") {
+            return value.as_str().map(str::to_owned);
+        }
+    }
+    None
+}
+
+#[test]
+fn client_calls_and_announced_changes() {
+    let bus = match TemporaryBus::start() {
+        Some(bus) => bus,
+        None => return,
+    };
+    let client = bus.connect();
+    // Listen before the player starts, to see the signal of its first metadata.
+    let announced = Arc::new(Mutex::new(Vec::new()));
+    client
+        .add_match(
+            PropertiesPropertiesChanged::match_rule(None, Some(&"/org/mpris/MediaPlayer2".into())),
+            {
+                let announced = announced.clone();
+                move |signal: PropertiesPropertiesChanged, _: &Connection, _: &Message| {
+                    announced.lock().unwrap().push(signal);
+                    true
+                }
+            },
+        )
+        .unwrap();
+
+    let mut controls = MediaControls::new(PlatformConfig::default()).unwrap();
+    let (event_handler, events) = event_sender();
+    controls
+        .attach_with_connection(bus.connect(), event_handler)
+        .unwrap();
+    controls
+        .set_metadata(MediaMetadata {
+            title: Some(TITLE),
+            artist: Some("Slowdive"),
+            album: Some("Souvlaki"),
+            ..Default::default()
+        })
+        .unwrap();
+
+    // The metadata is announced in a single PropertiesChanged of the Player interface.
+    let deadline = Instant::now() + TIMEOUT;
+    let announced_titles = loop {
+        client.process(TIMEOUT / 10).unwrap();
+        let announced = announced.lock().unwrap();
+        let titles: Vec<_> = announced
+            .iter()
+            .filter_map(|signal| {
+                let metadata = signal.changed_properties.get("Metadata")?;
+                assert_eq!(signal.interface_name, PLAYER_INTERFACE);
+                Some(title(&*metadata.0))
+            })
+            .collect();
+        if !titles.is_empty() || Instant::now() >= deadline {
+            break titles;
+        }
+    };
+    assert_eq!(announced_titles, [Some(TITLE.to_owned())]);
+
+    let player = client.with_proxy(BUS_NAME, "/org/mpris/MediaPlayer2", TIMEOUT);
+    let metadata: PropMap = player.get(PLAYER_INTERFACE, "Metadata").unwrap();
+    assert_eq!(
+        prop_cast::<String>(&metadata, "xesam:title").map(String::as_str),
+        Some(TITLE)
+    );
+    let playback_status: String = player.get(PLAYER_INTERFACE, "PlaybackStatus").unwrap();
+    assert_eq!(playback_status, "Stopped");
+
+    player
+        .method_call::<(), _, _, _>(PLAYER_INTERFACE, "PlayPause", ())
+        .unwrap();
+    assert_eq!(events.recv_timeout(TIMEOUT), Ok(MediaControlEvent::Toggle));
+
+    player.set(PLAYER_INTERFACE, "Volume", 0.5).unwrap();
+    assert_eq!(
+        events.recv_timeout(TIMEOUT),
+        Ok(MediaControlEvent::SetVolume(0.5))
+    );
+}